@@ -0,0 +1,176 @@
+// reading terms back out of an e-graph
+
+use std::collections::HashMap;
+
+use kanso_hashcons::Hc;
+use kanso_sets::Meta;
+
+use {Analysis, EGraph, Language};
+
+// a term as a list of nodes, where the children of each node are the positions of earlier
+// ones, so shared subterms are only written once. the last node is the root
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Expr<L> {
+  nodes: Vec<L>,
+}
+
+impl<L: Language> Expr<L> {
+  pub fn new() -> Self { Expr { nodes: Vec::new() } }
+
+  pub fn add(&mut self, node: L) -> Meta {
+    assert!(node.children().iter().all(|&c| usize::from(c) < self.nodes.len()), "expression refers forward");
+    self.nodes.push(node);
+    Meta::new(self.nodes.len() as u32 - 1).unwrap()
+  }
+
+  pub fn nodes(&self) -> &[L] { &self.nodes }
+
+  pub fn len(&self) -> usize { self.nodes.len() }
+
+  pub fn is_empty(&self) -> bool { self.nodes.is_empty() }
+
+  pub fn root(&self) -> Option<Meta> { Meta::new(self.nodes.len().checked_sub(1)? as u32) }
+}
+
+impl<L: Language> Default for Expr<L> {
+  fn default() -> Self { Self::new() }
+}
+
+impl<L: Language, N: Analysis<L>> EGraph<L, N> {
+  // adds every node of the expression, returning the class of its root
+  pub fn add_expr(&mut self, expr: &Expr<L>) -> Meta {
+    let mut ids: Vec<Meta> = Vec::with_capacity(expr.len());
+    for node in expr.nodes() {
+      let id = self.add(node.clone().map_children(|c| ids[usize::from(c)]));
+      ids.push(id)
+    }
+    *ids.last().expect("empty expression")
+  }
+
+  // the cheapest term in the class, given the cost of a node from the costs of its
+  // children. that should be more than any of theirs, or the cheapest term may not be
+  // finite. None if the class only contains infinite terms
+  pub fn extract<C: Ord + Clone, F: FnMut(&L, &[C]) -> C>(&self, class: Meta, mut cost: F) -> Option<(C, Expr<L>)> {
+    assert!(self.is_clean(), "extract needs a rebuild first");
+    let mut best: HashMap<Meta, (C, Hc<L>)> = HashMap::new();
+    let mut changed = true;
+    while changed {
+      changed = false;
+      for (c, class) in self.classes() {
+        for node in &class.nodes {
+          let costs: Option<Vec<C>> = node.children().iter().map(|&k| best.get(&self.find(k)).map(|b| b.0.clone())).collect();
+          if let Some(costs) = costs {
+            let total = cost(node, &costs);
+            if best.get(&c).is_none_or(|b| total < b.0) {
+              best.insert(c, (total, node.clone()));
+              changed = true
+            }
+          }
+        }
+      }
+    }
+    let root = self.find(class);
+    let total = best.get(&root)?.0.clone();
+    // build it children first, without recursing, as terms can be deep
+    let mut expr = Expr::new();
+    let mut built: HashMap<Meta, Meta> = HashMap::new();
+    let mut stack = vec![root];
+    while let Some(&c) = stack.last() {
+      if built.contains_key(&c) {
+        stack.pop();
+        continue
+      }
+      let node = &best[&c].1;
+      let todo: Vec<Meta> = node.children().iter().map(|&k| self.find(k)).filter(|k| !built.contains_key(k)).collect();
+      if todo.is_empty() {
+        let id = expr.add(node.get().clone().map_children(|k| built[&self.find(k)]));
+        built.insert(c, id);
+        stack.pop();
+      } else {
+        // so the first child is built first
+        stack.extend(todo.into_iter().rev())
+      }
+    }
+    Some((total, expr))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pattern::{Pattern, Rewrite, Var};
+  use testing::Arith::{self, *};
+
+  fn size(_: &Arith, children: &[usize]) -> usize { 1 + children.iter().sum::<usize>() }
+
+  fn rules() -> Vec<Rewrite<Arith>> {
+    let mut rules = Vec::new();
+    // x + y => y + x
+    let mut lhs = Pattern::new();
+    let (x, y) = (lhs.var(Var(0)), lhs.var(Var(1)));
+    lhs.node(Add([x, y]));
+    let mut rhs = Pattern::new();
+    let (x, y) = (rhs.var(Var(0)), rhs.var(Var(1)));
+    rhs.node(Add([y, x]));
+    rules.push(Rewrite::new("commute-add", lhs, rhs));
+    // x * 1 => x
+    let mut lhs = Pattern::new();
+    let x = lhs.var(Var(0));
+    let one = lhs.node(Num(1));
+    lhs.node(Mul([x, one]));
+    let mut rhs = Pattern::new();
+    rhs.var(Var(0));
+    rules.push(Rewrite::new("mul-one", lhs, rhs));
+    // 0 + x => x
+    let mut lhs = Pattern::new();
+    let zero = lhs.node(Num(0));
+    let x = lhs.var(Var(0));
+    lhs.node(Add([zero, x]));
+    let mut rhs = Pattern::new();
+    rhs.var(Var(0));
+    rules.push(Rewrite::new("zero-add", lhs, rhs));
+    rules
+  }
+
+  #[test]
+  fn simplifies() {
+    let mut g: EGraph<Arith> = EGraph::default();
+    // (a * 1) + 0, shared twice
+    let mut e = Expr::new();
+    let a = e.add(Sym(0));
+    let one = e.add(Num(1));
+    let a1 = e.add(Mul([a, one]));
+    let zero = e.add(Num(0));
+    let t = e.add(Add([a1, zero]));
+    e.add(Mul([t, t]));
+    let root = g.add_expr(&e);
+    assert_eq!(g.extract(root, size), Some((11, e.clone())));
+    let rounds = g.run(&rules(), 10);
+    assert!(0 < rounds && rounds < 10);
+    let (cost, best) = g.extract(root, size).unwrap();
+    assert_eq!(cost, 3);
+    let mut expected = Expr::new();
+    let a = expected.add(Sym(0));
+    expected.add(Mul([a, a]));
+    assert_eq!(best, expected);
+    // saturated, so running again changes nothing
+    assert_eq!(g.run(&rules(), 10), 0);
+  }
+
+  #[test]
+  fn cycles() {
+    let mut g: EGraph<Arith> = EGraph::default();
+    let a = g.add(Sym(0));
+    let one = g.add(Num(1));
+    let a1 = g.add(Mul([a, one]));
+    g.union(a, a1);
+    g.rebuild();
+    // a = a * 1 = (a * 1) * 1 = ...
+    let (cost, best) = g.extract(a1, size).unwrap();
+    assert_eq!((cost, best.len()), (1, 1));
+    let b = g.add(Sym(1));
+    let ba = g.add(Add([b, a]));
+    let (cost, _) = g.extract(ba, size).unwrap();
+    assert_eq!(cost, 3);
+  }
+}
@@ -0,0 +1,341 @@
+// e-graphs: disjoint sets of terms closed under congruence, so that once a and b are in
+// the same class, so are f(a) and f(b).
+//
+// the classes live in a SetsWith, so merging two of them merges their nodes, parents and
+// analysis data in one go, and every node is hash consed, so the memo from canonical nodes
+// to their classes only ever hashes the top of a node. as in egg, union just records which
+// classes need repair, and rebuild restores congruence for all of them in a batch.
+
+extern crate kanso_fun;
+extern crate kanso_hashcons;
+extern crate kanso_sets;
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::hash::Hash;
+use std::mem;
+
+use kanso_fun::algebra::Semigroup;
+use kanso_hashcons::{Constable, HashConstable, Hc};
+use kanso_sets::{Meta, SetsWith};
+
+pub mod extract;
+pub mod pattern;
+
+pub use extract::Expr;
+pub use pattern::{Pattern, Rewrite, Subst, Var};
+
+// an operator applied to classes
+pub trait Language: Hash + Eq + Clone + Debug {
+  fn children(&self) -> &[Meta];
+  fn children_mut(&mut self) -> &mut [Meta];
+
+  // the same operator, ignoring the children
+  fn matches(&self, other: &Self) -> bool;
+
+  fn map_children<F: FnMut(Meta) -> Meta>(mut self, mut f: F) -> Self {
+    for c in self.children_mut() {
+      *c = f(*c)
+    }
+    self
+  }
+}
+
+// facts about each class, made from its nodes and combined with op when classes merge.
+// op should be a semilattice join, or repairing the data after a merge may not settle
+pub trait Analysis<L: Language>: Sized {
+  type Data: Semigroup + PartialEq + Debug;
+  fn make(egraph: &EGraph<L, Self>, node: &L) -> Self::Data;
+}
+
+impl<L: Language> Analysis<L> for () {
+  type Data = ();
+  fn make(_: &EGraph<L, ()>, _: &L) {}
+}
+
+#[derive(Debug, Clone)]
+pub struct Class<L, D> {
+  // canonical and without duplicates after a rebuild
+  pub nodes: Vec<Hc<L>>,
+  // the nodes with a child in this class, and the classes they are in
+  parents: Vec<(Hc<L>, Meta)>,
+  pub data: D,
+}
+
+pub struct EGraph<L: Language, N: Analysis<L> = ()> {
+  pub analysis: N,
+  classes: SetsWith<Class<L, N::Data>>,
+  nodes: Constable<L>,
+  memo: HashMap<Hc<L>, Meta>,
+  // merged classes whose parents may have become congruent
+  pending: Vec<Meta>,
+  // classes whose data grew, so their parents' data may have to
+  analysis_pending: Vec<Meta>,
+}
+
+impl<L: Language, N: Analysis<L> + Default> Default for EGraph<L, N> {
+  fn default() -> Self { Self::new(N::default()) }
+}
+
+impl<L: Language, N: Analysis<L>> EGraph<L, N> {
+  pub fn new(analysis: N) -> Self {
+    EGraph {
+      analysis,
+      classes: SetsWith::new(),
+      nodes: Constable::new(),
+      memo: HashMap::new(),
+      pending: Vec::new(),
+      analysis_pending: Vec::new(),
+    }
+  }
+
+  // the number of distinct nodes
+  pub fn len(&self) -> usize { self.memo.len() }
+
+  pub fn is_empty(&self) -> bool { self.memo.is_empty() }
+
+  pub fn classes(&self) -> impl Iterator<Item = (Meta, &Class<L, N::Data>)> + '_ { self.classes.classes() }
+
+  pub fn find(&self, id: Meta) -> Meta { self.classes.find(id) }
+
+  pub fn class(&self, id: Meta) -> &Class<L, N::Data> { self.classes.get(id) }
+
+  pub fn data(&self, id: Meta) -> &N::Data { &self.class(id).data }
+
+  pub fn canonicalize(&self, node: L) -> L { node.map_children(|c| self.find(c)) }
+
+  fn intern(&mut self, node: L) -> Hc<L> {
+    let node = self.canonicalize(node);
+    self.nodes.mk(node)
+  }
+
+  // the class containing node, if there is one
+  pub fn lookup(&self, node: L) -> Option<Meta> {
+    let hc = self.nodes.find(&self.canonicalize(node))?;
+    Some(self.find(*self.memo.get(&hc)?))
+  }
+
+  pub fn add(&mut self, node: L) -> Meta {
+    let hc = self.intern(node);
+    if let Some(&id) = self.memo.get(&hc) {
+      return self.classes.find_mut(id)
+    }
+    let data = N::make(self, &hc);
+    let id = self.classes.make_set(Class { nodes: vec![hc.clone()], parents: Vec::new(), data });
+    for &c in hc.children() {
+      self.classes.value_mut(c).parents.push((hc.clone(), id))
+    }
+    self.memo.insert(hc, id);
+    id
+  }
+
+  // false if they were already equal. congruence isn't restored until the next rebuild
+  pub fn union(&mut self, a: Meta, b: Meta) -> bool {
+    let a = self.classes.find_mut(a);
+    let b = self.classes.find_mut(b);
+    if a == b {
+      return false
+    }
+    let mut grew = false;
+    let r = self.classes.union_with(a, b, |x, y| {
+      let Class { mut nodes, mut parents, data } = x;
+      nodes.extend(y.nodes);
+      parents.extend(y.parents);
+      let merged = data.clone().op(y.data.clone());
+      grew = merged != data || merged != y.data;
+      Class { nodes, parents, data: merged }
+    });
+    self.pending.push(r);
+    if grew {
+      self.analysis_pending.push(r)
+    }
+    true
+  }
+
+  pub fn is_clean(&self) -> bool { self.pending.is_empty() && self.analysis_pending.is_empty() }
+
+  // restores congruence and the analysis, returning how many more unions that took
+  pub fn rebuild(&mut self) -> usize {
+    let mut unions = 0;
+    while !self.is_clean() {
+      while let Some(c) = self.pending.pop() {
+        unions += self.repair(c)
+      }
+      while let Some(c) = self.analysis_pending.pop() {
+        self.repair_data(c)
+      }
+    }
+    self.tidy();
+    unions
+  }
+
+  // re-canonicalize the parents of c, merging any that have become congruent
+  fn repair(&mut self, c: Meta) -> usize {
+    let c = self.classes.find_mut(c);
+    let parents = mem::take(&mut self.classes.value_mut(c).parents);
+    let mut fresh: HashMap<Hc<L>, Meta> = HashMap::with_capacity(parents.len());
+    let mut unions = 0;
+    for (node, id) in parents {
+      self.memo.remove(&node);
+      let node = self.intern(node.get().clone());
+      let id = self.classes.find_mut(id);
+      if let Some(other) = self.memo.insert(node.clone(), id) {
+        if self.union(other, id) {
+          unions += 1
+        }
+      }
+      fresh.insert(node, id);
+    }
+    // c may have been merged into something else in the meantime
+    let c = self.classes.find_mut(c);
+    self.classes.value_mut(c).parents.extend(fresh);
+    unions
+  }
+
+  fn repair_data(&mut self, c: Meta) {
+    let c = self.classes.find_mut(c);
+    let parents = self.classes.value(c).parents.clone();
+    for (node, id) in parents {
+      let made = N::make(self, &node);
+      let id = self.classes.find_mut(id);
+      let class = self.classes.value_mut(id);
+      let data = class.data.clone().op(made);
+      if data != class.data {
+        class.data = data;
+        self.analysis_pending.push(id)
+      }
+    }
+  }
+
+  fn tidy(&mut self) {
+    let roots: Vec<Meta> = self.classes.classes().map(|(r, _)| r).collect();
+    for r in roots {
+      let nodes = mem::take(&mut self.classes.value_mut(r).nodes);
+      let mut canonical = Vec::with_capacity(nodes.len());
+      for node in nodes {
+        canonical.push(self.intern(node.get().clone()))
+      }
+      canonical.sort();
+      canonical.dedup();
+      self.classes.value_mut(r).nodes = canonical;
+    }
+  }
+}
+
+impl<L: Language, N: Analysis<L>> Debug for EGraph<L, N> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_map().entries(self.classes().map(|(r, class)| (r, (&class.nodes, &class.data)))).finish()
+  }
+}
+
+// a little arithmetic language for the tests
+#[cfg(test)]
+mod testing {
+  use kanso_sets::Meta;
+
+  use Language;
+
+  #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+  pub enum Arith {
+    Num(i64),
+    Sym(u32),
+    Add([Meta; 2]),
+    Mul([Meta; 2]),
+  }
+
+  use self::Arith::*;
+
+  impl Language for Arith {
+    fn children(&self) -> &[Meta] {
+      match self {
+        Add(xs) | Mul(xs) => xs,
+        _ => &[],
+      }
+    }
+    fn children_mut(&mut self) -> &mut [Meta] {
+      match self {
+        Add(xs) | Mul(xs) => xs,
+        _ => &mut [],
+      }
+    }
+    fn matches(&self, other: &Self) -> bool {
+      match (self, other) {
+        (Num(a), Num(b)) => a == b,
+        (Sym(a), Sym(b)) => a == b,
+        (Add(_), Add(_)) | (Mul(_), Mul(_)) => true,
+        _ => false,
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use kanso_fun::algebra::Max;
+  use testing::Arith::{self, *};
+
+  // constant folding. the constants in a class all agree, so Max just keeps the one
+  #[derive(Debug, Default)]
+  struct Fold;
+
+  impl Analysis<Arith> for Fold {
+    type Data = Option<Max<i64>>;
+    fn make(egraph: &EGraph<Arith, Fold>, node: &Arith) -> Option<Max<i64>> {
+      let arg = |i: usize| egraph.data(node.children()[i]).map(|m| m.0);
+      match node {
+        Num(n) => Some(Max(*n)),
+        Sym(_) => None,
+        Add(_) => Some(Max(arg(0)? + arg(1)?)),
+        Mul(_) => Some(Max(arg(0)? * arg(1)?)),
+      }
+    }
+  }
+
+  #[test]
+  fn congruence() {
+    let mut g: EGraph<Arith> = EGraph::default();
+    let a = g.add(Sym(0));
+    let b = g.add(Sym(1));
+    let c = g.add(Sym(2));
+    let ac = g.add(Add([a, c]));
+    let bc = g.add(Add([b, c]));
+    let acc = g.add(Mul([ac, c]));
+    let bcc = g.add(Mul([bc, c]));
+    assert_eq!(g.add(Add([a, c])), ac);
+    assert_ne!(g.find(acc), g.find(bcc));
+    assert!(g.union(a, b));
+    assert!(!g.union(b, a));
+    assert!(!g.is_clean());
+    assert_eq!(g.rebuild(), 2);
+    assert!(g.is_clean());
+    assert_eq!(g.find(ac), g.find(bc));
+    assert_eq!(g.find(acc), g.find(bcc));
+    assert_eq!(g.class(ac).nodes.len(), 1);
+    assert_eq!(g.lookup(Mul([bc, c])), Some(g.find(acc)));
+    assert_eq!(g.lookup(Mul([c, c])), None);
+    assert_eq!(g.classes().count(), 4);
+    assert_eq!(g.len(), 5);
+  }
+
+  #[test]
+  fn analysis() {
+    let mut g: EGraph<Arith, Fold> = EGraph::default();
+    let two = g.add(Num(2));
+    let three = g.add(Num(3));
+    let five = g.add(Add([two, three]));
+    assert_eq!(*g.data(five), Some(Max(5)));
+    let x = g.add(Sym(0));
+    let y = g.add(Sym(1));
+    let x2 = g.add(Mul([x, two]));
+    let yx2 = g.add(Add([y, x2]));
+    assert_eq!(*g.data(yx2), None);
+    // learning x and y propagates up through their parents
+    g.union(x, five);
+    g.union(y, three);
+    g.rebuild();
+    assert_eq!(*g.data(x), Some(Max(5)));
+    assert_eq!(*g.data(x2), Some(Max(10)));
+    assert_eq!(*g.data(yx2), Some(Max(13)));
+  }
+}
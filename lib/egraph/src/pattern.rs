@@ -0,0 +1,212 @@
+// patterns, for searching an e-graph, and rewrite rules built from them
+
+use kanso_sets::Meta;
+
+use {Analysis, EGraph, Language};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Var(pub u32);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Atom<L> {
+  Var(Var),
+  Node(L),
+}
+
+// built bottom up, like an Expr: the children of each node are the positions of earlier
+// atoms, and the last one is the root
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Pattern<L> {
+  atoms: Vec<Atom<L>>,
+}
+
+impl<L: Language> Pattern<L> {
+  pub fn new() -> Self { Pattern { atoms: Vec::new() } }
+
+  fn push(&mut self, atom: Atom<L>) -> Meta {
+    self.atoms.push(atom);
+    Meta::new(self.atoms.len() as u32 - 1).unwrap()
+  }
+
+  pub fn var(&mut self, v: Var) -> Meta { self.push(Atom::Var(v)) }
+
+  pub fn node(&mut self, node: L) -> Meta {
+    assert!(node.children().iter().all(|&c| usize::from(c) < self.atoms.len()), "pattern refers forward");
+    self.push(Atom::Node(node))
+  }
+
+  fn root(&self) -> usize {
+    assert!(!self.atoms.is_empty(), "empty pattern");
+    self.atoms.len() - 1
+  }
+
+  pub fn vars(&self) -> Vec<Var> {
+    let mut vars: Vec<Var> = self.atoms.iter().filter_map(|a| match a { Atom::Var(v) => Some(*v), _ => None }).collect();
+    vars.sort();
+    vars.dedup();
+    vars
+  }
+}
+
+impl<L: Language> Default for Pattern<L> {
+  fn default() -> Self { Self::new() }
+}
+
+// the class each variable matched
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Subst(Vec<(Var, Meta)>);
+
+impl Subst {
+  pub fn get(&self, v: Var) -> Option<Meta> { self.0.iter().find(|&&(w, _)| w == v).map(|&(_, m)| m) }
+
+  pub fn insert(&mut self, v: Var, m: Meta) -> Option<Meta> {
+    match self.0.iter_mut().find(|(w, _)| *w == v) {
+      Some((_, old)) => Some(std::mem::replace(old, m)),
+      None => {
+        self.0.push((v, m));
+        None
+      }
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rewrite<L> {
+  pub name: String,
+  pub lhs: Pattern<L>,
+  pub rhs: Pattern<L>,
+}
+
+impl<L: Language> Rewrite<L> {
+  pub fn new<S: Into<String>>(name: S, lhs: Pattern<L>, rhs: Pattern<L>) -> Self {
+    let name = name.into();
+    let bound = lhs.vars();
+    assert!(rhs.vars().iter().all(|v| bound.contains(v)), "{}: the right hand side has unbound variables", name);
+    Rewrite { name, lhs, rhs }
+  }
+}
+
+impl<L: Language, N: Analysis<L>> EGraph<L, N> {
+  // every class the pattern matches, with each way it does
+  pub fn search(&self, pattern: &Pattern<L>) -> Vec<(Meta, Subst)> {
+    assert!(self.is_clean(), "search needs a rebuild first");
+    let mut found = Vec::new();
+    for (c, _) in self.classes() {
+      let mut substs = Vec::new();
+      self.match_at(pattern, pattern.root(), c, &Subst::default(), &mut substs);
+      found.extend(substs.into_iter().map(|s| (c, s)))
+    }
+    found
+  }
+
+  pub fn search_class(&self, pattern: &Pattern<L>, class: Meta) -> Vec<Subst> {
+    assert!(self.is_clean(), "search needs a rebuild first");
+    let mut substs = Vec::new();
+    self.match_at(pattern, pattern.root(), self.find(class), &Subst::default(), &mut substs);
+    substs
+  }
+
+  fn match_at(&self, pattern: &Pattern<L>, at: usize, class: Meta, subst: &Subst, out: &mut Vec<Subst>) {
+    match &pattern.atoms[at] {
+      Atom::Var(v) => match subst.get(*v) {
+        Some(m) => {
+          if self.find(m) == self.find(class) {
+            out.push(subst.clone())
+          }
+        }
+        None => {
+          let mut subst = subst.clone();
+          subst.insert(*v, self.find(class));
+          out.push(subst)
+        }
+      },
+      Atom::Node(p) => {
+        for node in &self.class(class).nodes {
+          if !p.matches(node) || p.children().len() != node.children().len() {
+            continue
+          }
+          let mut substs = vec![subst.clone()];
+          for (&pc, &nc) in p.children().iter().zip(node.children()) {
+            let mut next = Vec::new();
+            for s in &substs {
+              self.match_at(pattern, usize::from(pc), nc, s, &mut next)
+            }
+            substs = next;
+          }
+          out.extend(substs)
+        }
+      }
+    }
+  }
+
+  // adds the pattern with its variables replaced
+  pub fn instantiate(&mut self, pattern: &Pattern<L>, subst: &Subst) -> Meta {
+    let mut ids: Vec<Meta> = Vec::with_capacity(pattern.atoms.len());
+    for atom in &pattern.atoms {
+      let id = match atom {
+        Atom::Var(v) => subst.get(*v).expect("unbound pattern variable"),
+        Atom::Node(node) => self.add(node.clone().map_children(|c| ids[usize::from(c)])),
+      };
+      ids.push(id)
+    }
+    ids[pattern.root()]
+  }
+
+  // applies every rule everywhere it matches until nothing changes or we have done limit
+  // rounds, and returns the number of rounds that changed something
+  pub fn run(&mut self, rules: &[Rewrite<L>], limit: usize) -> usize {
+    self.rebuild();
+    for round in 0..limit {
+      let found: Vec<Vec<(Meta, Subst)>> = rules.iter().map(|r| self.search(&r.lhs)).collect();
+      let mut changed = false;
+      for (rule, found) in rules.iter().zip(found) {
+        for (c, subst) in found {
+          let id = self.instantiate(&rule.rhs, &subst);
+          changed |= self.union(c, id)
+        }
+      }
+      self.rebuild();
+      if !changed {
+        return round
+      }
+    }
+    limit
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testing::Arith::{self, *};
+
+  #[test]
+  fn repeated_variables() {
+    let mut g: EGraph<Arith> = EGraph::default();
+    let a = g.add(Sym(0));
+    let b = g.add(Sym(1));
+    let ab = g.add(Add([a, b]));
+    let aa = g.add(Add([a, a]));
+    let mut p = Pattern::new();
+    let x = p.var(Var(0));
+    p.node(Add([x, x]));
+    let found = g.search(&p);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].0, aa);
+    assert_eq!(found[0].1.get(Var(0)), Some(a));
+    assert!(g.search_class(&p, ab).is_empty());
+    g.union(a, b);
+    g.rebuild();
+    // the two sums are now congruent
+    assert_eq!(g.search(&p).len(), 1);
+    assert_eq!(g.search_class(&p, ab).len(), 1);
+  }
+
+  #[test]
+  #[should_panic(expected = "unbound")]
+  fn unbound() {
+    let lhs: Pattern<Arith> = Pattern::new();
+    let mut rhs = Pattern::new();
+    rhs.var(Var(0));
+    Rewrite::new("bad", lhs, rhs);
+  }
+}
@@ -1,19 +1,19 @@
 use std::{cmp::Ordering, num::NonZeroUsize};
 use az::wrapping_cast;
 
-pub trait Semigroup: Sized {
+pub trait Semigroup: Sized + Clone {
   #[must_use]
   fn op(self, b:Self) -> Self;
 
   fn op_mut(&mut self, b: Self) {
-    *self = self.op(b);
+    *self = self.clone().op(b);
   }
   
   #[must_use]
   fn rep(self, n: NonZeroUsize) -> Self {
     let ng = n.get();
     if let Some(n2) = NonZeroUsize::new(ng >> 1) {
-      let self2 = self.op(self).rep(n2);
+      let self2 = self.clone().op(self.clone()).rep(n2);
       if ng & 1 == 0 {
         self2
       } else {
@@ -53,12 +53,10 @@ impl const Semigroup for ! {
 
 impl <A:Semigroup> const Semigroup for Option<A> {
   fn op(self, other:Self) -> Self {
-    match self {
-      None => other,
-      Some(a) => match other {
-        None => self,
-        Some(b) => Some(a.op(b))
-      }
+    match (self, other) {
+      (None, b) => b,
+      (a, None) => a,
+      (Some(a), Some(b)) => Some(a.op(b))
     }
   }
   fn op_mut(&mut self, other: Self) {
@@ -86,9 +84,9 @@ pub trait Monoid : Semigroup {
   #[must_use]
   fn rep(self,n:usize) -> Self {
     if n == 0 {
-      Monoid::id()
+      return Monoid::id()
     }
-    let self2 = Monoid::rep(self.op(self), n >> 1);
+    let self2 = Monoid::rep(self.clone().op(self.clone()), n >> 1);
     if n & 1 == 0 {
       self2
     } else {
@@ -127,7 +125,7 @@ pub trait Group : Monoid {
   fn inv(self) -> Self;
 
   fn inv_mut(&mut self) {
-    *self = self.inv();
+    *self = self.clone().inv();
   }
 
   #[must_use]
@@ -209,7 +207,7 @@ pub trait Action<M: Semigroup>: Sized {
 
 impl <M: Semigroup, A: Action<M>, B: Action<M>> const Action<M> for (A,B) {
   fn act(&self,m:M) -> Self {
-    (self.0.act(m), self.1.act(m))
+    (self.0.act(m.clone()), self.1.act(m))
   }
 }
 
@@ -258,11 +256,11 @@ pub struct Semi<A,B>(A,B);
 
 impl <A:Semigroup,B:RelativeSemigroup<A>> const Semigroup for Semi<A,B> {
   fn op(self, b:Self) -> Self {
-    Semi(self.0.op(b.0), self.1.op(b.1.act(self.0)))
+    Semi(self.0.clone().op(b.0), self.1.op(b.1.act(self.0)))
   }
 
   fn op_mut(&mut self, b: Self) {
-    self.1.op_mut(b.1.act(self.0));
+    self.1.op_mut(b.1.act(self.0.clone()));
     self.0.op_mut(b.0);
   }
 
@@ -278,14 +276,33 @@ impl <A:Monoid,B:RelativeMonoid<A>> const Monoid for Semi<A,B> {
 impl <A:Group,B:RelativeGroup<A>> const Group for Semi<A,B> {
   fn inv(self) -> Self {
     let ai = self.0.inv();
-    Self(ai,self.1.inv().act(ai))
+    Self(ai.clone(),self.1.inv().act(ai))
   }
   fn inv_mut(&mut self) {
     self.0.inv_mut();
     self.1.inv_mut();
-    self.1.act_mut(self.0);
+    self.1.act_mut(self.0.clone());
   }
   // todo optimize rep
 }
 
 
+// the larger of the two, preferring the first on ties
+#[derive(Debug,Copy,Clone,Hash,PartialEq,Eq,PartialOrd,Ord)]
+pub struct Max<A>(pub A);
+
+impl <A:Ord + Clone> Semigroup for Max<A> {
+  fn op(self, b:Self) -> Self {
+    if b.0 > self.0 { b } else { self }
+  }
+  fn rep(self, _: NonZeroUsize) -> Self { self }
+}
+
+// the rightmost of the two
+#[derive(Debug,Copy,Clone,Hash,PartialEq,Eq,PartialOrd,Ord)]
+pub struct Last<A>(pub A);
+
+impl <A:Clone> Semigroup for Last<A> {
+  fn op(self, b:Self) -> Self { b }
+  fn rep(self, _: NonZeroUsize) -> Self { self }
+}
@@ -1,5 +1,6 @@
 
 use std::iter::FromIterator;
+use std::mem;
 use hm::Q;
 use algebra::*;
 
@@ -38,11 +39,12 @@ impl <A:Clone> Semigroup for NonEmptyCat<A> {
     self.link(other)
   }
   fn op_mut(&mut self, other: Self) {
-    self.1 = Q::snoc(self.1,other);
+    self.1 = Q::snoc(mem::replace(&mut self.1,Q::nil()),other);
   }
 }
 
 // catenable output-restricted deque
+#[derive(Clone)]
 pub struct Cat<A>(Option<NonEmptyCat<A>>);
 
 impl <A> const Default for Cat<A> {
@@ -83,8 +85,8 @@ impl <A> Cat<A> {
   #[inline]
   #[must_use]
   fn uncons(&self) -> Option<(A,Cat<A>)> where A:Clone {
-    let p = self.0?;
-    Some((p.0,linkAll(p.1)))
+    let p = self.0.as_ref()?;
+    Some((p.0.clone(),linkAll(p.1.clone())))
   }
 }
 
@@ -0,0 +1,513 @@
+use std::cmp::{self, Ordering};
+use std::fmt::{self, Debug, Formatter};
+use std::iter::{FromIterator, FusedIterator, ExactSizeIterator, TrustedLen};
+use std::mem;
+
+use ::list::List;
+
+// A real-time persistent deque.
+//
+// Each end is a stack, and the two are kept within a factor of BALANCE of one another.
+// When one end outgrows the other, the excess is moved across by a rebuild that, like
+// the rotation in the Hood-Melville queue in `hm.rs`, is carried out a few steps at a
+// time while the deque keeps serving both ends, so every operation is worst-case O(1).
+//
+// While a rebuild is running, the stacks still hold the whole contents. The rebuild
+// works on snapshots, and only at the end is the copied run glued under the small end
+// and forgotten from the bottom of the big end.
+
+// how lopsided the ends may get before a rebuild starts
+const BALANCE: usize = 3;
+
+// rebuild steps taken per operation
+const STEPS: usize = 5;
+
+#[derive(Copy,Clone,PartialEq,Eq,Debug)]
+enum End { Front, Back }
+
+// a stack made of two runs: elements pushed since the last rebuild, and the run that
+// rebuild produced. only the first `len` elements of each run are live, which lets us
+// cut elements off the bottom without copying anything.
+#[derive(Clone)]
+struct Stack<A> {
+  lent: usize,
+  top: List<A>,
+  lenb: usize,
+  bot: List<A>
+}
+
+impl <A> Stack<A> {
+  const fn nil() -> Self {
+    Stack { lent: 0, top: List::nil(), lenb: 0, bot: List::nil() }
+  }
+
+  fn len(&self) -> usize { self.lent + self.lenb }
+
+  fn push(&mut self, a:A) {
+    self.top = List::cons(a, mem::replace(&mut self.top, List::nil()));
+    self.lent += 1;
+  }
+
+  // forget the bottom n live elements
+  fn truncate(&mut self, n: usize) {
+    let nb = cmp::min(n, self.lenb);
+    self.lenb -= nb;
+    self.lent -= n - nb;
+    self.collect();
+  }
+
+  // let go of runs with nothing live left in them
+  fn collect(&mut self) {
+    if self.lent == 0 { self.top = List::nil() }
+    if self.lenb == 0 { self.bot = List::nil() }
+  }
+}
+
+impl <A:Clone> Stack<A> {
+  fn pop(&mut self) -> Option<A> {
+    let x = if self.lent > 0 {
+      let (x, xs) = self.top.uncons()?;
+      self.lent -= 1;
+      self.top = xs;
+      x
+    } else {
+      let (x, xs) = self.bot.uncons()?;
+      self.lenb -= 1;
+      self.bot = xs;
+      x
+    };
+    self.collect();
+    Some(x)
+  }
+
+  // drops the top live element without cloning it
+  fn skip(&mut self) {
+    if self.lent > 0 {
+      self.lent -= 1;
+      self.top = self.top.tail().unwrap();
+    } else {
+      self.lenb -= 1;
+      self.bot = self.bot.tail().unwrap();
+    }
+    self.collect();
+  }
+
+  fn peek(&self) -> Option<A> {
+    if self.lent > 0 { self.top.head() } else if self.lenb > 0 { self.bot.head() } else { None }
+  }
+}
+
+// the state of an incremental rebuild, see `Rebuild::exec`
+#[derive(Clone)]
+enum Rot<A> {
+  // reversing the live elements of the small end onto rev
+  Reversing(usize,Stack<A>,List<A>,Stack<A>),
+  // walking past the elements the big end keeps
+  Skipping(usize,Stack<A>,List<A>),
+  // reversing the elements that move across onto acc
+  Moving(usize,Stack<A>,List<A>,List<A>),
+  // putting the surviving elements of the small end back on top of acc
+  Appending(usize,List<A>,List<A>),
+  Done(List<A>)
+}
+
+#[derive(Clone)]
+struct Rebuild<A> {
+  small: End,
+  // live elements of the small end when we started, and how many of those have since been popped
+  lens: usize,
+  popped: usize,
+  // elements pushed on the small end since we started that are still there
+  fresh: usize,
+  // elements moving across from the big end
+  moved: usize,
+  rot: Rot<A>
+}
+
+impl <A:Clone> Rebuild<A> {
+  fn start(small: End, s: &Stack<A>, b: &Stack<A>) -> Self {
+    let lens = s.len();
+    let moved = (lens + b.len()) / 2 - lens;
+    Rebuild {
+      small, lens, popped: 0, fresh: 0, moved,
+      rot: Rot::Reversing(lens, s.clone(), List::nil(), b.clone())
+    }
+  }
+
+  // the most steps this rebuild can still take
+  fn work(&self, lenb: usize) -> usize {
+    self.lens + lenb + self.lens
+  }
+
+  fn pushed(&mut self) { self.fresh += 1 }
+
+  fn popped(&mut self) {
+    if self.fresh > 0 { self.fresh -= 1 } else { self.popped += 1 }
+  }
+
+  // take one step. phase changes are free, so each step does a bounded amount of real work.
+  fn exec(&mut self) {
+    let rot = mem::replace(&mut self.rot, Rot::Done(List::nil()));
+    self.rot = match rot {
+      Rot::Reversing(0, _, rev, mut b) => {
+        let keep = b.len() - self.moved;
+        if keep > 0 {
+          b.skip();
+          Rot::Skipping(keep - 1, b, rev)
+        } else {
+          self.exec_moving(self.moved, b, rev, List::nil())
+        }
+      },
+      Rot::Reversing(n, mut s, rev, b) => {
+        let x = s.pop().unwrap();
+        Rot::Reversing(n-1, s, List::cons(x, rev), b)
+      },
+      Rot::Skipping(0, b, rev) => self.exec_moving(self.moved, b, rev, List::nil()),
+      Rot::Skipping(n, mut b, rev) => {
+        b.skip();
+        Rot::Skipping(n-1, b, rev)
+      },
+      Rot::Moving(n, b, rev, acc) => self.exec_moving(n, b, rev, acc),
+      Rot::Appending(done, rev, acc) => self.exec_appending(done, rev, acc),
+      done => done
+    }
+  }
+
+  fn exec_moving(&self, n: usize, mut b: Stack<A>, rev: List<A>, acc: List<A>) -> Rot<A> {
+    if n == 0 {
+      self.exec_appending(0, rev, acc)
+    } else {
+      let x = b.pop().unwrap();
+      Rot::Moving(n-1, b, rev, List::cons(x, acc))
+    }
+  }
+
+  // rev holds the small end's snapshot reversed, so the elements popped off of it since
+  // the rebuild started are the last ones we'd put back; stop short of them. we have to
+  // notice we are done as soon as we are, as the next pop would otherwise take one too many.
+  fn exec_appending(&self, done: usize, rev: List<A>, acc: List<A>) -> Rot<A> {
+    let keep = self.lens - self.popped;
+    if done == keep {
+      return Rot::Done(acc)
+    }
+    let (x, rev) = rev.uncons().unwrap();
+    let acc = List::cons(x, acc);
+    if done + 1 == keep {
+      Rot::Done(acc)
+    } else {
+      Rot::Appending(done+1, rev, acc)
+    }
+  }
+}
+
+// A real-time persistent deque
+#[derive(Clone)]
+pub struct Deque<A> {
+  front: Stack<A>,
+  back: Stack<A>,
+  rebuild: Option<Rebuild<A>>
+}
+
+impl<A> Deque<A> {
+  pub const fn nil() -> Deque<A> {
+    Deque { front: Stack::nil(), back: Stack::nil(), rebuild: None }
+  }
+
+  pub fn singleton(a:A) -> Deque<A> {
+    let mut front = Stack::nil();
+    front.push(a);
+    Deque { front, back: Stack::nil(), rebuild: None }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.length() == 0
+  }
+
+  pub fn length(&self) -> usize {
+    self.front.len() + self.back.len()
+  }
+
+  fn end(&self, e: End) -> &Stack<A> {
+    match e { End::Front => &self.front, End::Back => &self.back }
+  }
+
+  fn end_mut(&mut self, e: End) -> &mut Stack<A> {
+    match e { End::Front => &mut self.front, End::Back => &mut self.back }
+  }
+}
+
+impl<A:Clone> Deque<A> {
+  fn small_end(&self) -> Option<End> {
+    let (lf, lb) = (self.front.len(), self.back.len());
+    if lf > BALANCE * lb + 1 {
+      Some(End::Back)
+    } else if lb > BALANCE * lf + 1 {
+      Some(End::Front)
+    } else {
+      None
+    }
+  }
+
+  fn finish(&mut self) {
+    let done = match self.rebuild.as_ref() {
+      Some(Rebuild { rot: Rot::Done(_), .. }) => self.rebuild.take().unwrap(),
+      _ => return
+    };
+    if let Rot::Done(acc) = done.rot {
+      let big = self.end_mut(other(done.small));
+      big.truncate(done.moved);
+      let small = self.end_mut(done.small);
+      small.lent = done.fresh;
+      small.lenb = done.lens - done.popped + done.moved;
+      small.bot = acc;
+      small.collect();
+    }
+  }
+
+  // run the pending rebuild forward, or start one if the ends have drifted too far apart
+  fn check_mut(&mut self) {
+    if let Some(r) = self.rebuild.as_mut() {
+      for _ in 0..STEPS { r.exec() }
+      self.finish();
+    }
+    if self.rebuild.is_some() { return }
+    if let Some(small) = self.small_end() {
+      let (s, b) = (self.end(small), self.end(other(small)));
+      let mut r = Rebuild::start(small, s, b);
+      // the small end must not run dry before we are done. this is normally just STEPS,
+      // but be careful after a rebuild that finished while the ends were being hammered.
+      let lens = s.len();
+      let burst = STEPS + r.work(b.len()).saturating_sub(STEPS * (lens + 1));
+      for _ in 0..burst { r.exec() }
+      self.rebuild = Some(r);
+      self.finish();
+    }
+  }
+
+  fn check(mut self) -> Self {
+    self.check_mut();
+    self
+  }
+
+  fn push_mut(&mut self, e: End, x: A) {
+    self.end_mut(e).push(x);
+    if let Some(r) = self.rebuild.as_mut() {
+      if r.small == e { r.pushed() }
+    }
+    self.check_mut()
+  }
+
+  fn pop_mut(&mut self, e: End) -> Option<A> {
+    let x = match self.end_mut(e).pop() {
+      Some(x) => {
+        if let Some(r) = self.rebuild.as_mut() {
+          if r.small == e { r.popped() }
+        }
+        x
+      },
+      // the ends are balanced, so the other holds at most one element
+      None => {
+        debug_assert!(self.rebuild.is_none() && self.end(other(e)).len() <= 1);
+        self.end_mut(other(e)).pop()?
+      }
+    };
+    self.check_mut();
+    Some(x)
+  }
+
+  pub fn cons(self,x:A) -> Deque<A> {
+    let mut d = self;
+    d.cons_mut(x);
+    d
+  }
+
+  pub fn cons_mut(&mut self,x:A) {
+    self.push_mut(End::Front, x)
+  }
+
+  pub fn snoc(self,x:A) -> Deque<A> {
+    let mut d = self;
+    d.snoc_mut(x);
+    d
+  }
+
+  pub fn snoc_mut(&mut self,x:A) {
+    self.push_mut(End::Back, x)
+  }
+
+  pub fn uncons(self) -> Option<(A, Deque<A>)> {
+    let mut d = self;
+    let x = d.uncons_mut()?;
+    Some((x, d))
+  }
+
+  pub fn uncons_mut(&mut self) -> Option<A> {
+    self.pop_mut(End::Front)
+  }
+
+  pub fn unsnoc(self) -> Option<(Deque<A>, A)> {
+    let mut d = self;
+    let x = d.unsnoc_mut()?;
+    Some((d, x))
+  }
+
+  pub fn unsnoc_mut(&mut self) -> Option<A> {
+    self.pop_mut(End::Back)
+  }
+
+  pub fn peek_front(&self) -> Option<A> {
+    self.front.peek().or_else(|| self.back.peek())
+  }
+
+  pub fn peek_back(&self) -> Option<A> {
+    self.back.peek().or_else(|| self.front.peek())
+  }
+
+  pub fn reverse(self) -> Deque<A> {
+    Deque { front: self.back, back: self.front, rebuild: self.rebuild.map(|r| Rebuild { small: other(r.small), .. r }) }.check()
+  }
+}
+
+#[inline]
+fn other(e: End) -> End {
+  match e { End::Front => End::Back, End::Back => End::Front }
+}
+
+impl <A> Default for Deque<A> {
+  fn default() -> Deque<A> { Deque::nil() }
+}
+
+impl <A:Clone> FromIterator<A> for Deque<A> {
+  fn from_iter<T>(iter: T) -> Self where T: IntoIterator<Item = A> {
+    let mut r = Deque::nil();
+    for i in iter {
+        r.snoc_mut(i)
+    }
+    r
+  }
+}
+
+#[macro_export]
+macro_rules! deque {
+  [] => { $crate::deque::Deque::nil() };
+  [ $($x:expr),* ] => {{
+    let mut l = $crate::deque::Deque::nil();
+    $(
+       l.snoc_mut($x);
+    )*
+    l
+  }}
+}
+
+impl <A:Clone> Iterator for Deque<A> {
+  type Item = A;
+  fn next(&mut self) -> Option<A> {
+    self.uncons_mut()
+  }
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let n = self.length();
+    (n,Some(n))
+  }
+}
+
+impl <A:Clone> DoubleEndedIterator for Deque<A> {
+  fn next_back(&mut self) -> Option<A> {
+    self.unsnoc_mut()
+  }
+}
+
+// we keep returning None when exhausted.
+impl <A:Clone> FusedIterator for Deque<A> {}
+
+unsafe impl <A:Clone> TrustedLen for Deque<A> {}
+
+impl <A:Clone> ExactSizeIterator for Deque<A> {
+    fn len(&self) -> usize {
+      self.length()
+    }
+
+    fn is_empty(&self) -> bool { self.length() == 0 }
+}
+
+impl <A:Clone> Extend<A> for Deque<A> {
+    fn extend<T>(&mut self, iter: T) where T: IntoIterator<Item = A> {
+      for e in iter {
+        self.snoc_mut(e);
+      }
+    }
+
+    fn extend_one(&mut self, item: A) {
+      self.snoc_mut(item);
+    }
+
+    fn extend_reserve(&mut self, _: usize) {}
+}
+
+impl <A:Debug + Clone> Debug for Deque<A> {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    f.debug_list().entries(self.clone()).finish()
+  }
+}
+
+impl <A:PartialEq + Clone> PartialEq for Deque<A> {
+  fn eq(&self, other: &Self) -> bool {
+    self.length() == other.length() &&
+    Iterator::eq(self.clone(), other.clone())
+  }
+}
+impl <A:Eq + Clone> Eq for Deque<A> {}
+
+impl <A:PartialOrd + Clone> PartialOrd for Deque<A> {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Iterator::partial_cmp(self.clone(),other.clone())
+  }
+}
+
+impl <A:Ord + Clone> Ord for Deque<A> {
+  fn cmp(&self, other: &Self) -> Ordering {
+    Iterator::cmp(self.clone(),other.clone())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::VecDeque;
+
+  #[test]
+  fn it_works() {
+    let d: Deque<u32> = deque![1, 2, 3];
+    assert_eq!(d.clone().uncons().map(|p| p.0), Some(1));
+    assert_eq!(d.clone().unsnoc().map(|p| p.1), Some(3));
+    assert_eq!(d.clone().cons(0).snoc(4), (0..5).collect());
+    assert_eq!(d.clone().rev().collect::<Vec<_>>(), vec![3, 2, 1]);
+    assert_eq!(d.reverse(), deque![3, 2, 1]);
+  }
+
+  #[test]
+  fn against_vec_deque() {
+    let mut seed: u32 = 12345;
+    let mut d = Deque::nil();
+    let mut v = VecDeque::new();
+    let mut saved = Vec::new();
+    for i in 0..20000 {
+      seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+      // drift towards one end for a while, then the other, so rebuilds go both ways
+      let bias = if (i / 3000) % 2 == 0 { 0 } else { 2 };
+      match ((seed >> 16) % 6 + bias) % 6 {
+        0 | 1 => { d.cons_mut(i); v.push_front(i) },
+        2 => { d.snoc_mut(i); v.push_back(i) },
+        3 => assert_eq!(d.uncons_mut(), v.pop_front()),
+        _ => assert_eq!(d.unsnoc_mut(), v.pop_back()),
+      }
+      assert_eq!(d.length(), v.len());
+      assert_eq!(d.peek_front(), v.front().cloned());
+      assert_eq!(d.peek_back(), v.back().cloned());
+      if i % 1000 == 0 { saved.push((d.clone(), v.clone())) }
+    }
+    // older versions are unaffected by everything that happened since
+    for (d, v) in saved {
+      assert!(Iterator::eq(d, v.into_iter()));
+    }
+  }
+}
@@ -0,0 +1,107 @@
+// interval maps, as in section 4.8 of the finger tree paper
+//
+// entries are kept sorted by their low endpoint, and each subtree also knows the highest
+// high endpoint within it, so we can find the m intervals overlapping a query in
+// O(m log(n/m)).
+
+use super::*;
+
+// a closed interval
+#[derive(Debug,Copy,Clone,Hash,PartialEq,Eq,PartialOrd,Ord)]
+pub struct Interval<K> {
+  pub low: K,
+  pub high: K
+}
+
+impl <K:Ord> Interval<K> {
+  #[inline]
+  pub fn new(low: K, high: K) -> Self {
+    debug_assert!(low <= high);
+    Interval { low, high }
+  }
+
+  #[inline]
+  pub fn overlaps(&self, low: &K, high: &K) -> bool {
+    self.low <= *high && *low <= self.high
+  }
+}
+
+#[derive(Debug,Copy,Clone,Hash,PartialEq,Eq)]
+pub struct Entry<K,V> {
+  pub interval: Interval<K>,
+  pub value: V
+}
+
+impl <K:Ord + Clone,V> Measured for Entry<K,V> {
+  // the last low endpoint and the highest high endpoint
+  type Measure = (Option<Last<K>>, Option<Max<K>>);
+  #[inline]
+  fn measure(&self) -> Self::Measure {
+    (Some(Last(self.interval.low.clone())), Some(Max(self.interval.high.clone())))
+  }
+}
+
+pub struct IntervalMap<K:Ord + Clone,V>(FingerTree<Entry<K,V>>);
+
+impl <K:Ord + Clone,V> Clone for IntervalMap<K,V> {
+  fn clone(&self) -> Self { IntervalMap(self.0.clone()) }
+}
+
+impl <K:Ord + Clone,V> IntervalMap<K,V> {
+  #[inline]
+  #[must_use]
+  pub const fn new() -> Self { IntervalMap(FingerTree::nil()) }
+
+  #[inline]
+  pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+  // inserts after any entries with the same low endpoint
+  #[must_use]
+  pub fn insert(&self, interval: Interval<K>, value: V) -> Self {
+    let (l, r) = self.0.split(|m| m.0.as_ref().is_some_and(|k| k.0 > interval.low));
+    IntervalMap(l.snoc(Entry { interval, value }).append(&r))
+  }
+
+  // does any interval overlap [low, high]?
+  pub fn overlaps(&self, low: &K, high: &K) -> bool {
+    let (xs, _) = self.0.split(|m| m.0.as_ref().is_some_and(|k| k.0 > *high));
+    xs.measure().1.is_some_and(|k| k.0 >= *low)
+  }
+}
+
+impl <K:Ord + Clone,V:Clone> IntervalMap<K,V> {
+  // every entry overlapping [low, high], in order of their low endpoints
+  pub fn intersections(&self, low: &K, high: &K) -> Vec<(Interval<K>, V)> {
+    // only intervals starting at or before high can overlap
+    let (mut xs, _) = self.0.split(|m| m.0.as_ref().is_some_and(|k| k.0 > *high));
+    let mut result = Vec::new();
+    // and of those we want the ones ending at or after low
+    while xs.measure().1.is_some_and(|k| k.0 >= *low) {
+      let (_, r) = xs.split(|m| m.1.as_ref().is_some_and(|k| k.0 >= *low));
+      let (e, rest) = r.uncons().unwrap();
+      result.push((e.interval, e.value));
+      xs = rest;
+    }
+    result
+  }
+}
+
+impl <K:Ord + Clone,V> Default for IntervalMap<K,V> {
+  fn default() -> Self { IntervalMap::new() }
+}
+
+impl <K:Ord + Clone,V> FromIterator<(Interval<K>,V)> for IntervalMap<K,V> {
+  fn from_iter<T>(iter: T) -> Self where T: IntoIterator<Item = (Interval<K>,V)> {
+    let mut r = IntervalMap::new();
+    for (i, v) in iter {
+      r = r.insert(i, v)
+    }
+    r
+  }
+}
+
+impl <K:Ord + Clone + Debug,V:Debug + Clone> Debug for IntervalMap<K,V> {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    Debug::fmt(&self.0, f)
+  }
+}
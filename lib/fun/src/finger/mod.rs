@@ -0,0 +1,524 @@
+// 2-3 finger trees annotated with a monoidal measure
+//
+// Hinze and Paterson, "Finger trees: a simple general-purpose data structure"
+// http://www.staff.city.ac.uk/~ross/papers/FingerTree.pdf
+//
+// The paper's tree is the nested datatype FingerTree (Node a), which we can't monomorphize,
+// so nodes here are uniform: either a leaf holding an element or a 2-3 node of other nodes,
+// with the depth implicit in where the node sits. The spine is strict, so cons and snoc are
+// O(1) amortized but O(log n) worst case; split and concatenation are O(log n).
+
+use std::fmt::{self, Debug, Formatter};
+use std::iter::FromIterator;
+
+use ::algebra::*;
+use ::sync::Lrc;
+
+pub mod seq;
+pub mod prio;
+pub mod interval;
+
+pub trait Measured {
+  type Measure: Monoid + Clone;
+  fn measure(&self) -> Self::Measure;
+}
+
+enum Node<A:Measured> {
+  Leaf(A),
+  Node2(A::Measure, [Ptr<A>; 2]),
+  Node3(A::Measure, [Ptr<A>; 3])
+}
+
+type Ptr<A> = Lrc<Node<A>>;
+
+// digits hold 1-4 nodes, except transiently while a tree is being taken apart
+type Digit<A> = Vec<Ptr<A>>;
+
+impl <A:Measured> Node<A> {
+  fn measure(&self) -> A::Measure {
+    match self {
+      Node::Leaf(a) => a.measure(),
+      Node::Node2(m,_) | Node::Node3(m,_) => m.clone()
+    }
+  }
+
+  fn leaf(&self) -> &A {
+    match self {
+      Node::Leaf(a) => a,
+      _ => unreachable!()
+    }
+  }
+
+  // the children of a 2-3 node
+  fn children(&self) -> &[Ptr<A>] {
+    match self {
+      Node::Node2(_,xs) => xs,
+      Node::Node3(_,xs) => xs,
+      Node::Leaf(_) => unreachable!()
+    }
+  }
+}
+
+#[inline]
+fn leaf<A:Measured>(a: A) -> Ptr<A> {
+  Lrc::new(Node::Leaf(a))
+}
+
+#[inline]
+fn node2<A:Measured>(a: Ptr<A>, b: Ptr<A>) -> Ptr<A> {
+  Lrc::new(Node::Node2(a.measure().op(b.measure()), [a, b]))
+}
+
+#[inline]
+fn node3<A:Measured>(a: Ptr<A>, b: Ptr<A>, c: Ptr<A>) -> Ptr<A> {
+  Lrc::new(Node::Node3(a.measure().op(b.measure()).op(c.measure()), [a, b, c]))
+}
+
+fn measure_digit<A:Measured>(d: &[Ptr<A>]) -> A::Measure {
+  d.iter().fold(<A::Measure as Monoid>::id(), |m, x| m.op(x.measure()))
+}
+
+// group the 2-12 nodes left between two digits being glued together into 2-3 nodes
+fn nodes<A:Measured>(xs: Digit<A>) -> Digit<A> {
+  let mut n = xs.len();
+  let mut it = xs.into_iter();
+  let mut next = || it.next().unwrap();
+  let mut result = Vec::new();
+  while n > 0 {
+    match n {
+      2 | 4 => { result.push(node2(next(), next())); n -= 2 },
+      _ => { result.push(node3(next(), next(), next())); n -= 3 }
+    }
+  }
+  result
+}
+
+// split a digit at the first node where the accumulated measure satisfies p, or its last
+fn split_digit<A:Measured, F: Fn(&A::Measure) -> bool>(
+  p: &F,
+  mut i: A::Measure,
+  d: &[Ptr<A>]
+) -> (Digit<A>, Ptr<A>, Digit<A>) {
+  let last = d.len() - 1;
+  for (k, x) in d[..last].iter().enumerate() {
+    i = i.op(x.measure());
+    if p(&i) {
+      return (d[..k].to_vec(), x.clone(), d[k+1..].to_vec())
+    }
+  }
+  (d[..last].to_vec(), d[last].clone(), Vec::new())
+}
+
+// find the first node in a digit where the accumulated measure satisfies p, or its last
+fn lookup_digit<'a, A:Measured, F: Fn(&A::Measure) -> bool>(
+  p: &F,
+  mut i: A::Measure,
+  d: &'a [Ptr<A>]
+) -> (A::Measure, &'a Ptr<A>) {
+  let last = d.len() - 1;
+  for x in d[..last].iter() {
+    let j = i.clone().op(x.measure());
+    if p(&j) {
+      return (i, x)
+    }
+    i = j;
+  }
+  (i, &d[last])
+}
+
+fn lookup_node<'a, A:Measured, F: Fn(&A::Measure) -> bool>(p: &F, mut i: A::Measure, mut n: &'a Node<A>) -> &'a A {
+  loop {
+    if let Node::Leaf(a) = n {
+      break a
+    }
+    let (j, m) = lookup_digit(p, i, n.children());
+    i = j;
+    n = m;
+  }
+}
+
+struct Deep<A:Measured> {
+  measure: A::Measure,
+  prefix: Digit<A>,
+  middle: Tree<A>,
+  suffix: Digit<A>
+}
+
+enum Tree<A:Measured> {
+  Empty,
+  Single(Ptr<A>),
+  Deep(Lrc<Deep<A>>)
+}
+
+impl <A:Measured> Clone for Tree<A> {
+  fn clone(&self) -> Self {
+    match self {
+      Tree::Empty => Tree::Empty,
+      Tree::Single(x) => Tree::Single(x.clone()),
+      Tree::Deep(d) => Tree::Deep(d.clone())
+    }
+  }
+}
+
+impl <A:Measured> Tree<A> {
+  fn measure(&self) -> A::Measure {
+    match self {
+      Tree::Empty => Monoid::id(),
+      Tree::Single(x) => x.measure(),
+      Tree::Deep(d) => d.measure.clone()
+    }
+  }
+
+  fn deep(prefix: Digit<A>, middle: Tree<A>, suffix: Digit<A>) -> Tree<A> {
+    let measure = measure_digit(&prefix).op(middle.measure()).op(measure_digit(&suffix));
+    Tree::Deep(Lrc::new(Deep { measure, prefix, middle, suffix }))
+  }
+
+  fn from_digit(d: &[Ptr<A>]) -> Tree<A> {
+    d.iter().fold(Tree::Empty, |t, x| t.snoc(x.clone()))
+  }
+
+  fn cons(&self, a: Ptr<A>) -> Tree<A> {
+    match self {
+      Tree::Empty => Tree::Single(a),
+      Tree::Single(b) => Tree::deep(vec![a], Tree::Empty, vec![b.clone()]),
+      Tree::Deep(d) => {
+        let p = &d.prefix;
+        if p.len() == 4 {
+          let middle = d.middle.cons(node3(p[1].clone(), p[2].clone(), p[3].clone()));
+          Tree::deep(vec![a, p[0].clone()], middle, d.suffix.clone())
+        } else {
+          let mut prefix = Vec::with_capacity(p.len() + 1);
+          prefix.push(a);
+          prefix.extend(p.iter().cloned());
+          Tree::deep(prefix, d.middle.clone(), d.suffix.clone())
+        }
+      }
+    }
+  }
+
+  fn snoc(&self, a: Ptr<A>) -> Tree<A> {
+    match self {
+      Tree::Empty => Tree::Single(a),
+      Tree::Single(b) => Tree::deep(vec![b.clone()], Tree::Empty, vec![a]),
+      Tree::Deep(d) => {
+        let s = &d.suffix;
+        if s.len() == 4 {
+          let middle = d.middle.snoc(node3(s[0].clone(), s[1].clone(), s[2].clone()));
+          Tree::deep(d.prefix.clone(), middle, vec![s[3].clone(), a])
+        } else {
+          let mut suffix = s.clone();
+          suffix.push(a);
+          Tree::deep(d.prefix.clone(), d.middle.clone(), suffix)
+        }
+      }
+    }
+  }
+
+  // a deep tree whose prefix may have run out
+  fn deep_front(prefix: Digit<A>, middle: &Tree<A>, suffix: Digit<A>) -> Tree<A> {
+    if !prefix.is_empty() {
+      return Tree::deep(prefix, middle.clone(), suffix)
+    }
+    match middle.view_front() {
+      None => Tree::from_digit(&suffix),
+      Some((n, m)) => Tree::deep(n.children().to_vec(), m, suffix)
+    }
+  }
+
+  // a deep tree whose suffix may have run out
+  fn deep_back(prefix: Digit<A>, middle: &Tree<A>, suffix: Digit<A>) -> Tree<A> {
+    if !suffix.is_empty() {
+      return Tree::deep(prefix, middle.clone(), suffix)
+    }
+    match middle.view_back() {
+      None => Tree::from_digit(&prefix),
+      Some((m, n)) => Tree::deep(prefix, m, n.children().to_vec())
+    }
+  }
+
+  fn view_front(&self) -> Option<(Ptr<A>, Tree<A>)> {
+    match self {
+      Tree::Empty => None,
+      Tree::Single(x) => Some((x.clone(), Tree::Empty)),
+      Tree::Deep(d) => Some((
+        d.prefix[0].clone(),
+        Tree::deep_front(d.prefix[1..].to_vec(), &d.middle, d.suffix.clone())
+      ))
+    }
+  }
+
+  fn view_back(&self) -> Option<(Tree<A>, Ptr<A>)> {
+    match self {
+      Tree::Empty => None,
+      Tree::Single(x) => Some((Tree::Empty, x.clone())),
+      Tree::Deep(d) => {
+        let last = d.suffix.len() - 1;
+        Some((
+          Tree::deep_back(d.prefix.clone(), &d.middle, d.suffix[..last].to_vec()),
+          d.suffix[last].clone()
+        ))
+      }
+    }
+  }
+
+  fn app3(&self, ts: Digit<A>, other: &Tree<A>) -> Tree<A> {
+    match (self, other) {
+      (Tree::Empty, _) => ts.into_iter().rev().fold(other.clone(), |t, x| t.cons(x)),
+      (_, Tree::Empty) => ts.into_iter().fold(self.clone(), |t, x| t.snoc(x)),
+      (Tree::Single(x), _) => Tree::Empty.app3(ts, other).cons(x.clone()),
+      (_, Tree::Single(y)) => self.app3(ts, &Tree::Empty).snoc(y.clone()),
+      (Tree::Deep(l), Tree::Deep(r)) => {
+        let mut mid = l.suffix.clone();
+        mid.extend(ts);
+        mid.extend(r.prefix.iter().cloned());
+        Tree::deep(l.prefix.clone(), l.middle.app3(nodes(mid), &r.middle), r.suffix.clone())
+      }
+    }
+  }
+
+  // split a non-empty tree around the first node where i.op(measure so far) satisfies p
+  fn split_tree<F: Fn(&A::Measure) -> bool>(&self, p: &F, i: A::Measure) -> (Tree<A>, Ptr<A>, Tree<A>) {
+    match self {
+      Tree::Empty => unreachable!(),
+      Tree::Single(x) => (Tree::Empty, x.clone(), Tree::Empty),
+      Tree::Deep(d) => {
+        let vpr = i.clone().op(measure_digit(&d.prefix));
+        if p(&vpr) {
+          let (l, x, r) = split_digit(p, i, &d.prefix);
+          return (Tree::from_digit(&l), x, Tree::deep_front(r, &d.middle, d.suffix.clone()))
+        }
+        let vm = vpr.clone().op(d.middle.measure());
+        if p(&vm) {
+          let (ml, xs, mr) = d.middle.split_tree(p, vpr.clone());
+          let (l, x, r) = split_digit(p, vpr.op(ml.measure()), xs.children());
+          (Tree::deep_back(d.prefix.clone(), &ml, l), x, Tree::deep_front(r, &mr, d.suffix.clone()))
+        } else {
+          let (l, x, r) = split_digit(p, vm, &d.suffix);
+          (Tree::deep_back(d.prefix.clone(), &d.middle, l), x, Tree::from_digit(&r))
+        }
+      }
+    }
+  }
+
+  // like split_tree, but only finds the element
+  fn lookup<F: Fn(&A::Measure) -> bool>(&self, p: &F, i: A::Measure) -> &A {
+    match self {
+      Tree::Empty => unreachable!(),
+      Tree::Single(x) => lookup_node(p, i, x),
+      Tree::Deep(d) => {
+        let vpr = i.clone().op(measure_digit(&d.prefix));
+        if p(&vpr) {
+          let (j, x) = lookup_digit(p, i, &d.prefix);
+          return lookup_node(p, j, x)
+        }
+        let vm = vpr.clone().op(d.middle.measure());
+        if p(&vm) {
+          d.middle.lookup(p, vpr)
+        } else {
+          let (j, x) = lookup_digit(p, vm, &d.suffix);
+          lookup_node(p, j, x)
+        }
+      }
+    }
+  }
+}
+
+// A persistent finger tree of elements measured by some monoid
+pub struct FingerTree<A:Measured>(Tree<A>);
+
+impl <A:Measured> Clone for FingerTree<A> {
+  fn clone(&self) -> Self {
+    FingerTree(self.0.clone())
+  }
+}
+
+impl <A:Measured> FingerTree<A> {
+  #[inline]
+  #[must_use]
+  pub const fn nil() -> Self { FingerTree(Tree::Empty) }
+
+  #[inline]
+  #[must_use]
+  pub fn singleton(a: A) -> Self { FingerTree(Tree::Single(leaf(a))) }
+
+  #[inline]
+  pub fn is_empty(&self) -> bool { matches!(self.0, Tree::Empty) }
+
+  // the measure of the whole tree, O(1)
+  #[inline]
+  pub fn measure(&self) -> A::Measure { self.0.measure() }
+
+  #[must_use]
+  pub fn cons(self, a: A) -> Self { FingerTree(self.0.cons(leaf(a))) }
+
+  pub fn cons_mut(&mut self, a: A) { self.0 = self.0.cons(leaf(a)) }
+
+  #[must_use]
+  pub fn snoc(self, a: A) -> Self { FingerTree(self.0.snoc(leaf(a))) }
+
+  pub fn snoc_mut(&mut self, a: A) { self.0 = self.0.snoc(leaf(a)) }
+
+  pub fn front(&self) -> Option<&A> {
+    match &self.0 {
+      Tree::Empty => None,
+      Tree::Single(x) => Some(x.leaf()),
+      Tree::Deep(d) => Some(d.prefix[0].leaf())
+    }
+  }
+
+  pub fn back(&self) -> Option<&A> {
+    match &self.0 {
+      Tree::Empty => None,
+      Tree::Single(x) => Some(x.leaf()),
+      Tree::Deep(d) => Some(d.suffix[d.suffix.len() - 1].leaf())
+    }
+  }
+
+  // split the tree into the longest prefix whose measure doesn't satisfy p, and the rest.
+  // p should be monotone: once it holds of a prefix, it holds of every longer one.
+  pub fn split<F: Fn(&A::Measure) -> bool>(&self, p: F) -> (Self, Self) {
+    match self.0 {
+      Tree::Empty => (FingerTree::nil(), FingerTree::nil()),
+      _ if p(&self.measure()) => {
+        let (l, x, r) = self.0.split_tree(&p, Monoid::id());
+        (FingerTree(l), FingerTree(r.cons(x)))
+      },
+      _ => (self.clone(), FingerTree::nil())
+    }
+  }
+
+  // the first element at which p starts to hold of the measure of the prefix ending there
+  pub fn lookup<F: Fn(&A::Measure) -> bool>(&self, p: F) -> Option<&A> {
+    if self.is_empty() || !p(&self.measure()) {
+      None
+    } else {
+      Some(self.0.lookup(&p, Monoid::id()))
+    }
+  }
+
+  // concatenation, O(log(min(m,n)))
+  #[must_use]
+  pub fn append(&self, other: &Self) -> Self {
+    FingerTree(self.0.app3(Vec::new(), &other.0))
+  }
+}
+
+impl <A:Measured + Clone> FingerTree<A> {
+  pub fn uncons(self) -> Option<(A, Self)> {
+    let (x, t) = self.0.view_front()?;
+    Some((x.leaf().clone(), FingerTree(t)))
+  }
+
+  pub fn unsnoc(self) -> Option<(Self, A)> {
+    let (t, x) = self.0.view_back()?;
+    Some((FingerTree(t), x.leaf().clone()))
+  }
+}
+
+impl <A:Measured> Default for FingerTree<A> {
+  fn default() -> Self { FingerTree::nil() }
+}
+
+impl <A:Measured> Semigroup for FingerTree<A> {
+  fn op(self, other: Self) -> Self {
+    self.append(&other)
+  }
+}
+
+impl <A:Measured> Monoid for FingerTree<A> {
+  fn id() -> Self { FingerTree::nil() }
+}
+
+impl <A:Measured> FromIterator<A> for FingerTree<A> {
+  fn from_iter<T>(iter: T) -> Self where T: IntoIterator<Item = A> {
+    let mut r = FingerTree::nil();
+    for i in iter {
+      r.snoc_mut(i)
+    }
+    r
+  }
+}
+
+impl <A:Measured> Extend<A> for FingerTree<A> {
+  fn extend<T>(&mut self, iter: T) where T: IntoIterator<Item = A> {
+    for e in iter {
+      self.snoc_mut(e);
+    }
+  }
+}
+
+impl <A:Measured + Clone> Iterator for FingerTree<A> {
+  type Item = A;
+  fn next(&mut self) -> Option<A> {
+    let (x, t) = self.0.view_front()?;
+    self.0 = t;
+    Some(x.leaf().clone())
+  }
+}
+
+impl <A:Measured + Clone> DoubleEndedIterator for FingerTree<A> {
+  fn next_back(&mut self) -> Option<A> {
+    let (t, x) = self.0.view_back()?;
+    self.0 = t;
+    Some(x.leaf().clone())
+  }
+}
+
+impl <A:Measured + Debug + Clone> Debug for FingerTree<A> {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    f.debug_list().entries(self.clone()).finish()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::seq::Seq;
+  use super::prio::PQueue;
+  use super::interval::{Interval, IntervalMap};
+
+  #[test]
+  fn seq_works() {
+    let xs: Seq<u32> = (0..1000).collect();
+    assert_eq!(xs.len(), 1000);
+    for i in 0..1000 {
+      assert_eq!(xs.at(i), Some(&(i as u32)));
+    }
+    assert_eq!(xs.at(1000), None);
+    let (l, r) = xs.split_at(337);
+    assert_eq!(l.len(), 337);
+    assert_eq!(r.front(), Some(&337));
+    assert!(Iterator::eq(r.clone().append(&l), (337..1000).chain(0..337)));
+    assert!(Iterator::eq(xs.rev(), (0..1000).rev()));
+  }
+
+  #[test]
+  fn prio_works() {
+    let mut q = PQueue::new();
+    for (i, k) in [5, 1, 9, 3, 9, 7].iter().enumerate() {
+      q = q.insert(*k, i);
+    }
+    let mut out = Vec::new();
+    while let Some((k, v, rest)) = q.pop_max() {
+      out.push((k, v));
+      q = rest;
+    }
+    assert_eq!(out, vec![(9, 2), (9, 4), (7, 5), (5, 0), (3, 3), (1, 1)]);
+  }
+
+  #[test]
+  fn interval_works() {
+    let m: IntervalMap<u32, &str> = vec![
+      (Interval::new(10, 20), "a"),
+      (Interval::new(0, 5), "b"),
+      (Interval::new(3, 12), "c"),
+      (Interval::new(15, 16), "d")
+    ].into_iter().collect();
+    let names = |lo: u32, hi: u32| m.intersections(&lo, &hi).into_iter().map(|p| p.1).collect::<Vec<_>>();
+    assert_eq!(names(4, 4), vec!["b", "c"]);
+    assert_eq!(names(11, 15), vec!["c", "a", "d"]);
+    assert_eq!(names(6, 9), vec!["c"]);
+    assert_eq!(names(21, 30), Vec::<&str>::new());
+    assert!(m.overlaps(&13, &14) && !m.overlaps(&21, &30));
+  }
+}
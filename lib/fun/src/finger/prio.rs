@@ -0,0 +1,80 @@
+// max-priority queues, stable among equal priorities
+
+use super::*;
+
+#[derive(Debug,Copy,Clone,Hash,PartialEq,Eq)]
+pub struct Entry<K,V> {
+  pub key: K,
+  pub value: V
+}
+
+impl <K:Ord + Clone,V> Measured for Entry<K,V> {
+  type Measure = Option<Max<K>>;
+  #[inline]
+  fn measure(&self) -> Option<Max<K>> { Some(Max(self.key.clone())) }
+}
+
+pub struct PQueue<K:Ord + Clone,V>(FingerTree<Entry<K,V>>);
+
+impl <K:Ord + Clone,V> Clone for PQueue<K,V> {
+  fn clone(&self) -> Self { PQueue(self.0.clone()) }
+}
+
+impl <K:Ord + Clone,V> PQueue<K,V> {
+  #[inline]
+  #[must_use]
+  pub const fn new() -> Self { PQueue(FingerTree::nil()) }
+
+  #[inline]
+  pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+  // O(1) amortized
+  #[must_use]
+  pub fn insert(self, key: K, value: V) -> Self {
+    PQueue(self.0.snoc(Entry { key, value }))
+  }
+
+  // the oldest of the entries with the highest priority
+  pub fn max(&self) -> Option<&Entry<K,V>> {
+    let top = self.0.measure()?;
+    self.0.lookup(|m| m.as_ref() >= Some(&top))
+  }
+
+  #[must_use]
+  pub fn union(&self, other: &Self) -> Self {
+    PQueue(self.0.append(&other.0))
+  }
+}
+
+impl <K:Ord + Clone,V:Clone> PQueue<K,V> {
+  pub fn pop_max(&self) -> Option<(K, V, Self)> {
+    let top = self.0.measure()?;
+    let (l, r) = self.0.split(|m| m.as_ref() >= Some(&top));
+    let (e, r) = r.uncons()?;
+    Some((e.key, e.value, PQueue(l.append(&r))))
+  }
+}
+
+impl <K:Ord + Clone,V> Default for PQueue<K,V> {
+  fn default() -> Self { PQueue::new() }
+}
+
+impl <K:Ord + Clone,V> Semigroup for PQueue<K,V> {
+  fn op(self, other: Self) -> Self { self.union(&other) }
+}
+
+impl <K:Ord + Clone,V> Monoid for PQueue<K,V> {
+  fn id() -> Self { PQueue::new() }
+}
+
+impl <K:Ord + Clone,V> FromIterator<(K,V)> for PQueue<K,V> {
+  fn from_iter<T>(iter: T) -> Self where T: IntoIterator<Item = (K,V)> {
+    PQueue(iter.into_iter().map(|(key, value)| Entry { key, value }).collect())
+  }
+}
+
+impl <K:Ord + Clone + Debug,V:Debug + Clone> Debug for PQueue<K,V> {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    Debug::fmt(&self.0, f)
+  }
+}
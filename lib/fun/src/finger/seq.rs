@@ -0,0 +1,152 @@
+// sequences with O(log n) indexing, splitting and concatenation
+
+use std::cmp::Ordering;
+use std::fmt::{self, Debug, Formatter};
+use std::iter::{FromIterator, FusedIterator, ExactSizeIterator, TrustedLen};
+
+use super::*;
+
+// an element measured by how many of them there are
+#[derive(Debug,Copy,Clone,Hash,PartialEq,Eq,PartialOrd,Ord)]
+#[repr(transparent)]
+pub struct Elem<A>(pub A);
+
+impl <A> Measured for Elem<A> {
+  type Measure = usize;
+  #[inline]
+  fn measure(&self) -> usize { 1 }
+}
+
+#[repr(transparent)]
+pub struct Seq<A>(FingerTree<Elem<A>>);
+
+impl <A> Clone for Seq<A> {
+  fn clone(&self) -> Self { Seq(self.0.clone()) }
+}
+
+impl <A> Seq<A> {
+  #[inline]
+  #[must_use]
+  pub const fn nil() -> Self { Seq(FingerTree::nil()) }
+
+  #[inline]
+  #[must_use]
+  pub fn singleton(a: A) -> Self { Seq(FingerTree::singleton(Elem(a))) }
+
+  #[inline]
+  pub fn len(&self) -> usize { self.0.measure() }
+
+  #[inline]
+  pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+  #[must_use]
+  pub fn cons(self, a: A) -> Self { Seq(self.0.cons(Elem(a))) }
+
+  #[must_use]
+  pub fn snoc(self, a: A) -> Self { Seq(self.0.snoc(Elem(a))) }
+
+  pub fn front(&self) -> Option<&A> { Some(&self.0.front()?.0) }
+
+  pub fn back(&self) -> Option<&A> { Some(&self.0.back()?.0) }
+
+  pub fn at(&self, i: usize) -> Option<&A> {
+    Some(&self.0.lookup(|n| *n > i)?.0)
+  }
+
+  // the first i elements, and the rest
+  pub fn split_at(&self, i: usize) -> (Self, Self) {
+    let (l, r) = self.0.split(|n| *n > i);
+    (Seq(l), Seq(r))
+  }
+
+  #[must_use]
+  pub fn append(&self, other: &Self) -> Self { Seq(self.0.append(&other.0)) }
+}
+
+impl <A:Clone> Seq<A> {
+  pub fn uncons(self) -> Option<(A, Self)> {
+    let (x, t) = self.0.uncons()?;
+    Some((x.0, Seq(t)))
+  }
+
+  pub fn unsnoc(self) -> Option<(Self, A)> {
+    let (t, x) = self.0.unsnoc()?;
+    Some((Seq(t), x.0))
+  }
+}
+
+impl <A> Default for Seq<A> {
+  fn default() -> Self { Seq::nil() }
+}
+
+impl <A> Semigroup for Seq<A> {
+  fn op(self, other: Self) -> Self { self.append(&other) }
+}
+
+impl <A> Monoid for Seq<A> {
+  fn id() -> Self { Seq::nil() }
+}
+
+impl <A> FromIterator<A> for Seq<A> {
+  fn from_iter<T>(iter: T) -> Self where T: IntoIterator<Item = A> {
+    Seq(iter.into_iter().map(Elem).collect())
+  }
+}
+
+impl <A> Extend<A> for Seq<A> {
+  fn extend<T>(&mut self, iter: T) where T: IntoIterator<Item = A> {
+    self.0.extend(iter.into_iter().map(Elem))
+  }
+}
+
+impl <A:Clone> Iterator for Seq<A> {
+  type Item = A;
+  fn next(&mut self) -> Option<A> {
+    Some(self.0.next()?.0)
+  }
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let n = self.len();
+    (n, Some(n))
+  }
+}
+
+impl <A:Clone> DoubleEndedIterator for Seq<A> {
+  fn next_back(&mut self) -> Option<A> {
+    Some(self.0.next_back()?.0)
+  }
+}
+
+impl <A:Clone> FusedIterator for Seq<A> {}
+
+unsafe impl <A:Clone> TrustedLen for Seq<A> {}
+
+impl <A:Clone> ExactSizeIterator for Seq<A> {
+  fn len(&self) -> usize { self.0.measure() }
+  fn is_empty(&self) -> bool { self.0.is_empty() }
+}
+
+impl <A:Debug + Clone> Debug for Seq<A> {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    f.debug_list().entries(self.clone()).finish()
+  }
+}
+
+impl <A:PartialEq + Clone> PartialEq for Seq<A> {
+  fn eq(&self, other: &Self) -> bool {
+    self.len() == other.len() &&
+    Iterator::eq(self.clone(), other.clone())
+  }
+}
+impl <A:Eq + Clone> Eq for Seq<A> {}
+
+impl <A:PartialOrd + Clone> PartialOrd for Seq<A> {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Iterator::partial_cmp(self.clone(), other.clone())
+  }
+}
+
+impl <A:Ord + Clone> Ord for Seq<A> {
+  fn cmp(&self, other: &Self) -> Ordering {
+    Iterator::cmp(self.clone(), other.clone())
+  }
+}
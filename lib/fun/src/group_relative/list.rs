@@ -1,38 +1,142 @@
+use std::fmt::{self, Debug, Formatter};
+use std::iter::FromIterator;
+
 use ::algebra::*;
 use ::list::List as L;
 
-pub struct List<G:Group,A:GroupAction<G>>(G,L<A>);
+// a list whose elements are stored relative to a group element.
+//
+// List(g, l) holds the elements of l, each acted on by g. Since we can invert g, cons can
+// store its argument pre-divided by g, so unlike the monoid-relative list the cells never
+// need to carry actions of their own.
+pub struct List<G,A>(G,L<A>);
+
+impl <G:Clone,A> Clone for List<G,A> {
+  #[inline]
+  fn clone(&self) -> Self { List(self.0.clone(), self.1.clone()) }
+}
 
 impl <G:Group,A:GroupAction<G>> List<G,A> {
-  pub const fn nil() -> Self { List(Monoid::id(),L::nil()) }
-  pub fn cons(x:A, xs: Self) -> Self { 
-    List(xs.0,L::cons(x.act(xs.0.inv()),xs.1))
+  #[inline]
+  #[must_use]
+  pub fn nil() -> Self { List(Monoid::id(),L::nil()) }
+
+  #[inline]
+  #[must_use]
+  pub fn cons(x:A, xs: Self) -> Self {
+    let List(g, l) = xs;
+    let y = x.act(g.clone().inv());
+    List(g, L::cons(y, l))
   }
-  // if you are going to clone the result, and are dropping this reference, you can use this
-  pub fn uncons(self) -> Option<(A,Self)> where A:Clone {
-    let p = self.1.take()?;
-    Some((p.0.act(self.0), List(self.0,p.1)))
+
+  #[inline]
+  pub fn is_empty(&self) -> bool { self.1.is_empty() }
+
+  #[must_use]
+  pub fn uncons(&self) -> Option<(A,Self)> {
+    let (a, t) = self.1.peek()?;
+    Some((a.act(self.0.clone()), List(self.0.clone(), t.clone())))
   }
-  pub fn head(&self) -> Option<A> where A:Clone {
-    Some(self.1.head()?.act(self.0))
+
+  #[inline]
+  #[must_use]
+  pub fn head(&self) -> Option<A> {
+    Some(self.1.peek()?.0.act(self.0.clone()))
   }
-  pub fn tail(&self) -> Option<Self> where A:Clone {
-    Some(List(self.0,self.1.tail()?))
+
+  #[inline]
+  #[must_use]
+  pub fn tail(&self) -> Option<Self> {
+    Some(List(self.0.clone(), self.1.peek()?.1.clone()))
   }
 }
 
-impl <G:Group+Clone,A:GroupAction<G>+Clone> Action<G> for List<G,A> {
-  fn act(&self,m:G) -> Self {
-    List(m.op(self.0.clone()),self.1.clone())
+// O(1)
+impl <G:Group,A:GroupAction<G>> Action<G> for List<G,A> {
+  fn act(&self, m:G) -> Self {
+    List(self.0.clone().op(m), self.1.clone())
+  }
+  fn act_mut(&mut self, m:G) {
+    self.0.op_mut(m)
   }
 }
 
-/*
-impl <G,A:GroupAction<G>> Semigroup for List<G,A> {
-  fn op(self,other: Self) -> Self {
-    match self.take() {
-      Some((a,as)
+impl <G:Group,A:GroupAction<G>> MonoidAction<G> for List<G,A> {}
+
+// O(n) in the length of the first list, whose elements are moved to be relative to
+// the group element of the second
+impl <G:Group,A:GroupAction<G>> Semigroup for List<G,A> {
+  fn op(self, ys:Self) -> Self {
+    let d = self.0.op(ys.0.clone().inv());
+    let mut xs = Vec::new();
+    let mut rest = &self.1;
+    while let Some((x, t)) = rest.peek() {
+      xs.push(x.act(d.clone()));
+      rest = t;
     }
+    let List(g, l) = ys;
+    List(g, xs.into_iter().rev().fold(l, |acc, x| L::cons(x, acc)))
+  }
+}
+
+impl <G:Group,A:GroupAction<G>> Monoid for List<G,A> {
+  fn id() -> Self { List::nil() }
+}
+
+impl <G:Group,A:GroupAction<G>> Default for List<G,A> {
+  fn default() -> Self { List::nil() }
+}
+
+impl <G:Group,A:GroupAction<G>> Iterator for List<G,A> {
+  type Item = A;
+  fn next(&mut self) -> Option<A> {
+    let (x, xs) = self.uncons()?;
+    *self = xs;
+    Some(x)
+  }
+}
+
+impl <G:Group,A:GroupAction<G>> FromIterator<A> for List<G,A> {
+  fn from_iter<T>(iter: T) -> Self where T: IntoIterator<Item = A> {
+    let xs: Vec<A> = iter.into_iter().collect();
+    List(Monoid::id(), xs.into_iter().rev().fold(L::nil(), |acc, x| L::cons(x, acc)))
+  }
+}
+
+impl <G:Group,A:GroupAction<G> + Debug> Debug for List<G,A> {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    f.debug_list().entries(self.clone()).finish()
+  }
+}
+
+impl <G:Group,A:GroupAction<G> + PartialEq> PartialEq for List<G,A> {
+  fn eq(&self, other: &Self) -> bool {
+    Iterator::eq(self.clone(), other.clone())
+  }
+}
+impl <G:Group,A:GroupAction<G> + Eq> Eq for List<G,A> {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // de Bruijn levels, shifted by weakening and strengthening
+  #[derive(Debug,Copy,Clone,PartialEq,Eq)]
+  struct Lvl(i32);
+
+  impl Action<i32> for Lvl {
+    fn act(&self, m:i32) -> Lvl { Lvl(self.0 + m) }
+  }
+  impl MonoidAction<i32> for Lvl {}
+
+  #[test]
+  fn it_works() {
+    let xs: List<i32,Lvl> = vec![Lvl(0), Lvl(1), Lvl(2)].into_iter().collect();
+    let ys = List::cons(Lvl(7), xs.act(10));
+    assert_eq!(ys.head(), Some(Lvl(7)));
+    assert_eq!(ys.clone().collect::<Vec<_>>(), vec![Lvl(7), Lvl(10), Lvl(11), Lvl(12)]);
+    assert_eq!(ys.act(-3).op(xs.act(1)).collect::<Vec<_>>(),
+      vec![Lvl(4), Lvl(7), Lvl(8), Lvl(9), Lvl(1), Lvl(2), Lvl(3)]);
+    assert_eq!(ys.act(5).act(-5), ys);
   }
 }
-*/
\ No newline at end of file
@@ -0,0 +1,106 @@
+use std::fmt::{self, Debug, Formatter};
+
+use ::algebra::*;
+use ::skew::{self, Skew as S};
+
+// a skew-binary random access list whose elements are stored relative to a group element,
+// for environments that need to be shifted as a whole, e.g. when going under a binder.
+pub struct Skew<G,A>(G,S<A>);
+
+impl <G:Clone,A> Clone for Skew<G,A> {
+  #[inline]
+  fn clone(&self) -> Self { Skew(self.0.clone(), self.1.clone()) }
+}
+
+impl <G:Group,A:GroupAction<G>> Skew<G,A> {
+  #[inline]
+  #[must_use]
+  pub fn nil() -> Self { Skew(Monoid::id(), skew::nil()) }
+
+  #[inline]
+  #[must_use]
+  pub fn cons(x:A, xs: Self) -> Self {
+    let Skew(g, s) = xs;
+    let y = x.act(g.clone().inv());
+    Skew(g, skew::cons(y, s))
+  }
+
+  #[must_use]
+  pub fn uncons(&self) -> Option<(A,Self)> {
+    let (a, t) = self.1.uncons()?;
+    Some((a.act(self.0.clone()), Skew(self.0.clone(), t)))
+  }
+
+  // O(log n)
+  #[must_use]
+  pub fn at(&self, n: usize) -> Option<A> {
+    Some(self.1.at(n)?.act(self.0.clone()))
+  }
+
+  // O(log n)
+  #[must_use]
+  pub fn drop(&self, n: usize) -> Self {
+    Skew(self.0.clone(), self.1.drop(n))
+  }
+}
+
+// O(1)
+impl <G:Group,A:GroupAction<G>> Action<G> for Skew<G,A> {
+  fn act(&self, m:G) -> Self {
+    Skew(self.0.clone().op(m), self.1.clone())
+  }
+  fn act_mut(&mut self, m:G) {
+    self.0.op_mut(m)
+  }
+}
+
+impl <G:Group,A:GroupAction<G>> MonoidAction<G> for Skew<G,A> {}
+
+impl <G:Group,A:GroupAction<G>> Default for Skew<G,A> {
+  fn default() -> Self { Skew::nil() }
+}
+
+impl <G:Group,A:GroupAction<G>> Iterator for Skew<G,A> {
+  type Item = A;
+  fn next(&mut self) -> Option<A> {
+    let (x, xs) = self.uncons()?;
+    *self = xs;
+    Some(x)
+  }
+}
+
+impl <G:Group,A:GroupAction<G> + Debug> Debug for Skew<G,A> {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    f.debug_list().entries(self.clone()).finish()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[derive(Debug,Copy,Clone,PartialEq,Eq)]
+  struct Lvl(i32);
+
+  impl Action<i32> for Lvl {
+    fn act(&self, m:i32) -> Lvl { Lvl(self.0 + m) }
+  }
+  impl MonoidAction<i32> for Lvl {}
+
+  // weakening an environment by a binder shifts every level in it, without visiting any
+  #[test]
+  fn shift_env() {
+    let mut env: Skew<i32,Lvl> = Skew::nil();
+    for i in 0..100 {
+      env = Skew::cons(Lvl(i), env);
+    }
+    let weakened = env.act(1);
+    assert_eq!(env.at(0), Some(Lvl(99)));
+    assert_eq!(weakened.at(0), Some(Lvl(100)));
+    assert_eq!(weakened.at(99), Some(Lvl(1)));
+    let extended = Skew::cons(Lvl(0), weakened);
+    assert_eq!(extended.at(0), Some(Lvl(0)));
+    assert_eq!(extended.at(1), Some(Lvl(100)));
+    assert_eq!(extended.act(-1).drop(1).at(98), Some(Lvl(1)));
+  }
+}
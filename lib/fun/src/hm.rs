@@ -1,6 +1,7 @@
 use std::convert::TryFrom;
 use std::cmp::Ordering;
 use std::iter::{FromIterator, FusedIterator, ExactSizeIterator, TrustedLen};
+use std::mem;
 //use algebra::*;
 
 use ::list::List;
@@ -93,13 +94,13 @@ impl<A> Q<A> {
 }
 impl<A:Clone> Q<A> {
   fn exec2(&self) -> Self {
-    match self.state.exec().exec() {
-      Rot::Done(newf) => Q { f: newf, state: Rot::Idle, .. *self },
-      newstate => Q { state: newstate, .. *self }
+    match self.state.clone().exec().exec() {
+      Rot::Done(newf) => Q { f: newf, state: Rot::Idle, .. self.clone() },
+      newstate => Q { state: newstate, .. self.clone() }
     }
   }
   fn exec2_mut(&mut self) {
-    match self.state.exec().exec() {
+    match mem::replace(&mut self.state, Rot::Idle).exec().exec() {
       Rot::Done(newf) => { self.f = newf; self.state = Rot::Idle },
       newstate => self.state = newstate
     }
@@ -110,8 +111,8 @@ impl<A:Clone> Q<A> {
     } else {
       let qp = Q {
         lenf: self.lenf + self.lenr, 
-        f: self.f, 
-        state: Rot::Reversing(0,self.f,List::nil(),self.r,List::nil()), 
+        f: self.f.clone(), 
+        state: Rot::Reversing(0,self.f.clone(),List::nil(),self.r.clone(),List::nil()), 
         lenr: 0, 
         r: List::nil()
       };
@@ -122,8 +123,8 @@ impl<A:Clone> Q<A> {
     if self.lenr > self.lenf {
       *self = Q {
         lenf: self.lenf + self.lenr, 
-        f: self.f, 
-        state: Rot::Reversing(0,self.f,List::nil(),self.r,List::nil()), 
+        f: self.f.clone(), 
+        state: Rot::Reversing(0,self.f.clone(),List::nil(),self.r.clone(),List::nil()), 
         lenr: 0, 
         r: List::nil()
       };
@@ -137,7 +138,7 @@ impl<A:Clone> Q<A> {
 
   pub fn snoc_mut(&mut self,x:A) {
     self.lenr += 1;
-    self.r = List::cons(x,self.r);
+    self.r = List::cons(x,mem::replace(&mut self.r,List::nil()));
     self.check_mut();
   }
 
@@ -166,11 +167,11 @@ impl <A:Clone> FromIterator<A> for Q<A> {
 
 #[macro_export]
 macro_rules! q {
-  [] => { $crate::q::Q::nil() };
+  [] => { $crate::hm::Q::nil() };
   [ $($x:expr),* ] => {{
-    let mut l = $crate::q::Q::nil();
+    let mut l = $crate::hm::Q::nil();
     $(
-       l = $crate::q::Q::snoc(l,$x);
+       l = $crate::hm::Q::snoc(l,$x);
     )*
     // TODO reverse parameter order with macro tricks, then just construct directly
     l
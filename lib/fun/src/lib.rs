@@ -0,0 +1,27 @@
+#![feature(const_trait_impl)]
+#![feature(never_type)]
+#![feature(extend_one)]
+#![feature(exact_size_is_empty)]
+#![feature(trusted_len)]
+
+// === Linter configuration
+#![allow(dead_code)]
+#![warn(missing_copy_implementations)]
+#![warn(trivial_numeric_casts)]
+
+extern crate az;
+extern crate cfg_if;
+extern crate serde;
+
+pub mod algebra;
+pub mod sync;
+pub mod list;
+#[macro_use]
+pub mod skew;
+pub mod hm;
+#[macro_use]
+pub mod deque;
+pub mod cat;
+pub mod finger;
+pub mod monoid_relative;
+pub mod group_relative;
@@ -2,9 +2,13 @@ use ::sync::Lrc;
 use ::algebra::*;
 
 #[repr(transparent)]
-#[derive(Clone)]
 pub struct List<A>(Option<Lrc<(A,List<A>)>>);
 
+impl <A> Clone for List<A> {
+  #[inline]
+  fn clone(&self) -> Self { List(self.0.clone()) }
+}
+
 impl <A> List<A> {
   #[inline]
   #[must_use]
@@ -12,12 +16,16 @@ impl <A> List<A> {
   #[inline]
   #[must_use]
   pub fn cons(x:A, xs: List<A>) -> List<A> { List(Some(Lrc::new((x,xs)))) }
+
+  #[inline]
+  #[must_use]
+  pub fn peek(&self) -> Option<&(A,Self)> { self.0.as_ref().map(|p| p.as_ref()) }
+
+  #[inline]
+  pub fn is_empty(&self) -> bool { self.0.is_none() }
 }
 
 impl <A:Clone> List<A> {
-  //#[must_use]
-  //pub fn peek(&self) -> Option<&(A,Self)> { self.0.as_ref() }
-
   // if you are going to clone the result, and are dropping this reference, you can use this
   #[inline]
   #[must_use]
@@ -38,15 +46,18 @@ impl <A:Clone> List<A> {
   }
 }
 
-impl <A> Semigroup for List<A> {
+impl <A:Clone> Semigroup for List<A> {
   fn op(self, ys:Self) -> Self {
-    match self.0.as_ref() {
-      None => ys,
-      Some(xxs) => List::cons(xxs.0,xxs.1.op(ys))
+    let mut xs = Vec::new();
+    let mut rest = &self;
+    while let Some((x, t)) = rest.peek() {
+      xs.push(x.clone());
+      rest = t;
     }
+    xs.into_iter().rev().fold(ys, |acc, x| List::cons(x, acc))
   }
 }
 
-impl <A> Monoid for List<A> {
+impl <A:Clone> Monoid for List<A> {
   fn id() -> Self { List(None) }
 }
\ No newline at end of file
@@ -1,33 +1,131 @@
+use std::fmt::{self, Debug, Formatter};
+use std::iter::FromIterator;
+
+use ::algebra::*;
 use ::sync::Lrc;
 
-#[repr(transparent)]
-pub struct List<M,A>(Option<Lrc<(M,A,List<M,A>)>>);
+// a list carrying an action that is pending on all of its elements.
+//
+// List(m, Some((a, as))) holds a.act(m) followed by the elements of as, each acted on by m,
+// so acting on the whole list just composes onto m, and the action is pushed one cell
+// further down each time we look at a cell.
+pub struct List<M,A>(M,Option<Lrc<(A,List<M,A>)>>);
+
+impl <M:Clone,A> Clone for List<M,A> {
+  #[inline]
+  fn clone(&self) -> Self { List(self.0.clone(), self.1.clone()) }
+}
+
+impl <M:Monoid,A:MonoidAction<M>> List<M,A> {
+  #[inline]
+  #[must_use]
+  pub fn nil() -> Self { List(Monoid::id(), None) }
+
+  #[inline]
+  #[must_use]
+  pub fn cons(x:A, xs: Self) -> Self { List(Monoid::id(), Some(Lrc::new((x,xs)))) }
+
+  #[inline]
+  pub fn is_empty(&self) -> bool { self.1.is_none() }
+
+  #[must_use]
+  pub fn uncons(&self) -> Option<(A,Self)> {
+    let (a, t) = self.1.as_ref()?.as_ref();
+    Some((a.act(self.0.clone()), t.act(self.0.clone())))
+  }
+
+  #[inline]
+  #[must_use]
+  pub fn head(&self) -> Option<A> {
+    Some(self.1.as_ref()?.0.act(self.0.clone()))
+  }
 
-impl <M,A:MonoidAction<M>> List<M,A> {
-  pub const fn nil() -> List<A> { List(None) }
-  pub fn cons(x:A, xs: List<A>) -> List<A> { List(Some(Rc::new((x,xs)))) }
+  #[inline]
+  #[must_use]
+  pub fn tail(&self) -> Option<Self> {
+    Some(self.1.as_ref()?.1.act(self.0.clone()))
+  }
+}
 
-  // if you are going to clone the result, and are dropping this reference, you can use this
-  pub fn take(&self) -> Option<(A,List<A>)> where A:Clone {
-    Some(self.0?.unwrap_or_clone())
+// O(1)
+impl <M:Monoid,A:MonoidAction<M>> Action<M> for List<M,A> {
+  fn act(&self, m:M) -> Self {
+    List(self.0.clone().op(m), self.1.clone())
   }
-  pub fn tail(&self) -> Option<&List<A>> {
-    Some(self.0?.as_ref()).1
+  fn act_mut(&mut self, m:M) {
+    self.0.op_mut(m)
   }
+}
+
+impl <M:Monoid,A:MonoidAction<M>> MonoidAction<M> for List<M,A> {}
+
+// O(n) in the length of the first list
+impl <M:Monoid,A:MonoidAction<M>> Semigroup for List<M,A> {
+  fn op(self, ys:Self) -> Self {
+    let xs: Vec<A> = self.collect();
+    xs.into_iter().rev().fold(ys, |acc, x| List::cons(x, acc))
+  }
+}
+
+impl <M:Monoid,A:MonoidAction<M>> Monoid for List<M,A> {
+  fn id() -> Self { List::nil() }
+}
+
+impl <M:Monoid,A:MonoidAction<M>> Default for List<M,A> {
+  fn default() -> Self { List::nil() }
+}
+
+impl <M:Monoid,A:MonoidAction<M>> Iterator for List<M,A> {
+  type Item = A;
+  fn next(&mut self) -> Option<A> {
+    let (x, xs) = self.uncons()?;
+    *self = xs;
+    Some(x)
+  }
+}
+
+impl <M:Monoid,A:MonoidAction<M>> FromIterator<A> for List<M,A> {
+  fn from_iter<T>(iter: T) -> Self where T: IntoIterator<Item = A> {
+    let xs: Vec<A> = iter.into_iter().collect();
+    xs.into_iter().rev().fold(List::nil(), |acc, x| List::cons(x, acc))
+  }
+}
+
+impl <M:Monoid,A:MonoidAction<M> + Debug> Debug for List<M,A> {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    f.debug_list().entries(self.clone()).finish()
+  }
+}
+
+impl <M:Monoid,A:MonoidAction<M> + PartialEq> PartialEq for List<M,A> {
+  fn eq(&self, other: &Self) -> bool {
+    Iterator::eq(self.clone(), other.clone())
+  }
+}
+impl <M:Monoid,A:MonoidAction<M> + Eq> Eq for List<M,A> {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // de Bruijn levels, shifted by weakening
+  #[derive(Debug,Copy,Clone,PartialEq,Eq)]
+  struct Lvl(u32);
 
-  pub fn head(self) -> Option<A> where A:Clone {
-    match self.0?.try_unwrap() {
-    Err((m,a,_)) => { 
-      Some(a.act(m)) 
-    },
-    Ok(p) => p.1.act(p.0)
-    }
+  impl Action<u32> for Lvl {
+    fn act(&self, m:u32) -> Lvl { Lvl(self.0 + m) }
   }
+  impl MonoidAction<u32> for Lvl {}
 
-  pub fn take_tail(self) -> Option<&List<A>> {
-    match self.0?.try_unwrap() {
-    Err(rp) => Some(rp.as_ref().1.clone()),
-    Ok(p) => p.1
-    }
+  #[test]
+  fn it_works() {
+    let xs: List<u32,Lvl> = vec![Lvl(0), Lvl(1), Lvl(2)].into_iter().collect();
+    let ys = List::cons(Lvl(7), xs.act(10));
+    assert_eq!(ys.head(), Some(Lvl(7)));
+    assert_eq!(ys.tail().unwrap().head(), Some(Lvl(10)));
+    let zs = ys.act(1).op(xs.clone());
+    assert_eq!(zs.collect::<Vec<_>>(), vec![Lvl(8), Lvl(11), Lvl(12), Lvl(13), Lvl(0), Lvl(1), Lvl(2)]);
+    assert_eq!(xs.act(2).act(3), xs.act(5));
+    assert!(List::<u32,Lvl>::nil().act(3).is_empty());
   }
 }
@@ -1,2 +1,2 @@
-pub use list;
-
+pub mod list;
+pub mod skew;
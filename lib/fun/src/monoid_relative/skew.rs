@@ -0,0 +1,349 @@
+// skew-binary random access lists with lazily applied monoid actions
+//
+// like `skew::Skew`, but the spine cells and the edges of the trees carry actions that are
+// pending on everything below them, so acting on the whole list is O(1) and the actions
+// are composed on the way down when we look something up. unlike `group_relative::Skew`
+// this needs no inverses, so it works for e.g. renumbering spans after an edit, where the
+// renumbering can't be undone.
+//
+// throughout, act(act(x,m),n) = act(x,m.op(n)), so when descending we compose the inner
+// action onto the left of the one accumulated so far.
+
+use std::cmp::Ordering;
+use std::fmt::{self, Debug, Formatter};
+use std::iter::FromIterator;
+use std::mem;
+
+use ::algebra::*;
+use ::sync::Lrc;
+
+struct Node<M,A> {
+  elem: A,
+  kids: Option<Kids<M,A>>
+}
+
+type Tree<M,A> = Lrc<Node<M,A>>;
+
+// the two children, each with the action pending on it
+type Kids<M,A> = (M, Tree<M,A>, M, Tree<M,A>);
+
+struct Cell<M,A> {
+  size: usize,
+  tree_act: M,
+  tree: Tree<M,A>,
+  rest_act: M,
+  rest: Orc<M,A>
+}
+
+type Orc<M,A> = Option<Lrc<Cell<M,A>>>;
+
+// Skew(m, cells) holds the elements of the cells, each acted on by m
+pub struct Skew<M,A>(M,Orc<M,A>);
+
+impl <M:Clone,A> Clone for Skew<M,A> {
+  #[inline]
+  fn clone(&self) -> Self { Skew(self.0.clone(), self.1.clone()) }
+}
+
+#[inline]
+fn tip<M,A>(a: A) -> Tree<M,A> {
+  Lrc::new(Node { elem: a, kids: None })
+}
+
+#[inline]
+fn cell<M,A>(size: usize, tree_act: M, tree: Tree<M,A>, rest_act: M, rest: Orc<M,A>) -> Orc<M,A> {
+  Some(Lrc::new(Cell { size, tree_act, tree, rest_act, rest }))
+}
+
+fn at_tree<M:Monoid,A:MonoidAction<M>>(mut k: usize, mut ts: usize, mut t: &Tree<M,A>, mut acc: M) -> Option<A> {
+  loop {
+    if k == 0 {
+      break Some(t.elem.act(acc))
+    }
+    let (ml, l, mr, r) = t.kids.as_ref()?;
+    k -= 1;
+    ts >>= 1;
+    if k < ts {
+      acc = ml.clone().op(acc);
+      t = l;
+    } else {
+      k -= ts;
+      acc = mr.clone().op(acc);
+      t = r;
+    }
+  }
+}
+
+// drop 0 < k < ts elements from t, whose elements are acted on by tacc, in front of rest,
+// whose elements are acted on by pending. the cells we build carry their whole action.
+fn drop_tree<M:Monoid,A:MonoidAction<M>>(
+  mut k: usize,
+  mut ts: usize,
+  mut t: &Tree<M,A>,
+  mut tacc: M,
+  mut pending: M,
+  mut rest: Orc<M,A>
+) -> Skew<M,A> {
+  loop {
+    ts >>= 1;
+    match t.kids.as_ref() {
+      Some((ml, l, mr, r)) => {
+        let la = ml.clone().op(tacc.clone());
+        let ra = mr.clone().op(tacc);
+        let bnd = 1 + ts;
+        match k.cmp(&bnd) {
+          Ordering::Less => {
+            rest = cell(ts, ra, r.clone(), mem::replace(&mut pending, Monoid::id()), rest);
+            if k == 1 {
+              break Skew(Monoid::id(), cell(ts, la, l.clone(), Monoid::id(), rest))
+            }
+            t = l;
+            tacc = la;
+            k -= 1;
+          },
+          Ordering::Equal => break Skew(Monoid::id(), cell(ts, ra, r.clone(), pending, rest)),
+          Ordering::Greater => {
+            t = r;
+            tacc = ra;
+            k -= bnd;
+          }
+        }
+      },
+      None => break Skew(pending, rest)
+    }
+  }
+}
+
+impl <M:Monoid,A:MonoidAction<M>> Skew<M,A> {
+  #[inline]
+  #[must_use]
+  pub fn nil() -> Self { Skew(Monoid::id(), None) }
+
+  #[inline]
+  pub fn is_empty(&self) -> bool { self.1.is_none() }
+
+  #[must_use]
+  pub fn cons(x:A, xs: Self) -> Self {
+    let Skew(m, spine) = xs;
+    if let Some(c0) = spine.as_ref() {
+      if let Some(c1) = c0.rest.as_ref() {
+        if c0.size == c1.size {
+          let la = c0.tree_act.clone().op(m.clone());
+          let ra = c1.tree_act.clone().op(c0.rest_act.clone()).op(m.clone());
+          let rest_act = c1.rest_act.clone().op(c0.rest_act.clone()).op(m);
+          let tree = Lrc::new(Node { elem: x, kids: Some((la, c0.tree.clone(), ra, c1.tree.clone())) });
+          return Skew(Monoid::id(), cell(c0.size + c1.size + 1, Monoid::id(), tree, rest_act, c1.rest.clone()))
+        }
+      }
+    }
+    Skew(Monoid::id(), cell(1, Monoid::id(), tip(x), m, spine))
+  }
+
+  #[must_use]
+  pub fn uncons(&self) -> Option<(A,Self)> {
+    let c = self.1.as_ref()?;
+    let head = c.tree.elem.act(c.tree_act.clone().op(self.0.clone()));
+    let tail = match c.tree.kids.as_ref() {
+      None => Skew(c.rest_act.clone().op(self.0.clone()), c.rest.clone()),
+      Some((ml, l, mr, r)) => {
+        let half = c.size >> 1;
+        let rest = cell(half, mr.clone().op(c.tree_act.clone()), r.clone(), c.rest_act.clone(), c.rest.clone());
+        Skew(self.0.clone(), cell(half, ml.clone().op(c.tree_act.clone()), l.clone(), Monoid::id(), rest))
+      }
+    };
+    Some((head, tail))
+  }
+
+  // O(log n)
+  #[must_use]
+  pub fn at(&self, mut k: usize) -> Option<A> {
+    let mut acc = self.0.clone();
+    let mut x = &self.1;
+    loop {
+      let c = x.as_ref()?;
+      if k < c.size {
+        break at_tree(k, c.size, &c.tree, c.tree_act.clone().op(acc))
+      }
+      k -= c.size;
+      acc = c.rest_act.clone().op(acc);
+      x = &c.rest;
+    }
+  }
+
+  // O(log n)
+  #[must_use]
+  pub fn drop(&self, mut k: usize) -> Self {
+    let mut acc = self.0.clone();
+    let mut x = &self.1;
+    loop {
+      if k == 0 {
+        break Skew(acc, x.clone())
+      }
+      let c = match x.as_ref() {
+        None => break Skew::nil(),
+        Some(c) => c
+      };
+      match c.size.cmp(&k) {
+        Ordering::Less => {
+          k -= c.size;
+          acc = c.rest_act.clone().op(acc);
+          x = &c.rest;
+        },
+        Ordering::Equal => break Skew(c.rest_act.clone().op(acc), c.rest.clone()),
+        Ordering::Greater => {
+          let tacc = c.tree_act.clone().op(acc.clone());
+          let pending = c.rest_act.clone().op(acc);
+          break drop_tree(k, c.size, &c.tree, tacc, pending, c.rest.clone())
+        }
+      }
+    }
+  }
+
+  // O(log n)
+  pub fn length(&self) -> usize {
+    let mut n = 0;
+    let mut x = &self.1;
+    while let Some(c) = x.as_ref() {
+      n += c.size;
+      x = &c.rest;
+    }
+    n
+  }
+}
+
+// O(1)
+impl <M:Monoid,A:MonoidAction<M>> Action<M> for Skew<M,A> {
+  fn act(&self, m:M) -> Self {
+    Skew(self.0.clone().op(m), self.1.clone())
+  }
+  fn act_mut(&mut self, m:M) {
+    self.0.op_mut(m)
+  }
+}
+
+impl <M:Monoid,A:MonoidAction<M>> MonoidAction<M> for Skew<M,A> {}
+
+impl <M:Monoid,A:MonoidAction<M>> Default for Skew<M,A> {
+  fn default() -> Self { Skew::nil() }
+}
+
+impl <M:Monoid,A:MonoidAction<M>> Iterator for Skew<M,A> {
+  type Item = A;
+  fn next(&mut self) -> Option<A> {
+    let (x, xs) = self.uncons()?;
+    *self = xs;
+    Some(x)
+  }
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let n = self.length();
+    (n, Some(n))
+  }
+}
+
+impl <M:Monoid,A:MonoidAction<M>> ExactSizeIterator for Skew<M,A> {
+  fn len(&self) -> usize { self.length() }
+  fn is_empty(&self) -> bool { self.1.is_none() }
+}
+
+impl <M:Monoid,A:MonoidAction<M>> FromIterator<A> for Skew<M,A> {
+  fn from_iter<T>(iter: T) -> Self where T: IntoIterator<Item = A> {
+    let xs: Vec<A> = iter.into_iter().collect();
+    xs.into_iter().rev().fold(Skew::nil(), |acc, x| Skew::cons(x, acc))
+  }
+}
+
+impl <M:Monoid,A:MonoidAction<M> + Debug> Debug for Skew<M,A> {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    f.debug_list().entries(self.clone()).finish()
+  }
+}
+
+impl <M:Monoid,A:MonoidAction<M> + PartialEq> PartialEq for Skew<M,A> {
+  fn eq(&self, other: &Self) -> bool {
+    self.length() == other.length() && Iterator::eq(self.clone(), other.clone())
+  }
+}
+impl <M:Monoid,A:MonoidAction<M> + Eq> Eq for Skew<M,A> {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // levels, shifted by m
+  #[derive(Debug,Copy,Clone,PartialEq,Eq)]
+  struct Lvl(u32);
+
+  impl Action<u32> for Lvl {
+    fn act(&self, m:u32) -> Lvl { Lvl(self.0 + m) }
+  }
+  impl MonoidAction<u32> for Lvl {}
+
+  // x -> a*x + b, wrapping. op is "this, then that", which doesn't commute, and an even
+  // a can't be undone
+  #[derive(Debug,Copy,Clone,PartialEq,Eq)]
+  struct Affine(u32, u32);
+
+  impl Semigroup for Affine {
+    fn op(self, n: Affine) -> Affine {
+      Affine(n.0.wrapping_mul(self.0), n.0.wrapping_mul(self.1).wrapping_add(n.1))
+    }
+  }
+  impl Monoid for Affine {
+    fn id() -> Affine { Affine(1, 0) }
+  }
+
+  impl Action<Affine> for Lvl {
+    fn act(&self, m:Affine) -> Lvl { Lvl(m.0.wrapping_mul(self.0).wrapping_add(m.1)) }
+  }
+  impl MonoidAction<Affine> for Lvl {}
+
+  #[test]
+  fn it_works() {
+    let xs: Skew<u32,Lvl> = (0..10).map(Lvl).collect();
+    assert_eq!(xs.at(3), Some(Lvl(3)));
+    let ys = Skew::cons(Lvl(100), xs.act(5));
+    assert_eq!(ys.at(0), Some(Lvl(100)));
+    assert_eq!(ys.at(4), Some(Lvl(8)));
+    assert_eq!(ys.act(1).drop(2).collect::<Vec<_>>(), (7..16).map(Lvl).collect::<Vec<_>>());
+    // doubling then adding one is not adding one then doubling
+    let zs: Skew<Affine,Lvl> = (0..10).map(Lvl).collect();
+    assert_eq!(zs.act(Affine(2, 0)).act(Affine(1, 1)).at(3), Some(Lvl(7)));
+    assert_eq!(zs.act(Affine(1, 1)).act(Affine(2, 0)).at(3), Some(Lvl(8)));
+  }
+
+  // random conses, actions, unconses and drops, checked against a vector acted on an
+  // element at a time
+  fn against_vec<M:Monoid + Clone>(action: impl Fn(u32) -> M) where Lvl: MonoidAction<M> {
+    let mut seed: u32 = 99;
+    let mut xs: Skew<M,Lvl> = Skew::nil();
+    let mut v: Vec<Lvl> = Vec::new(); // head at the end
+    for i in 0..3000 {
+      seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+      match (seed >> 16) % 8 {
+        0..=2 => { xs = Skew::cons(Lvl(i), xs); v.push(Lvl(i)) },
+        3 => { xs = xs.act(action(i)); for x in v.iter_mut() { x.act_mut(action(i)) } },
+        4 => if let Some((x, rest)) = xs.uncons() {
+          assert_eq!(Some(x), v.pop());
+          xs = rest
+        },
+        5 => {
+          let k = (seed as usize >> 3) % 4;
+          xs = xs.drop(k);
+          let n = v.len().saturating_sub(k);
+          v.truncate(n);
+        },
+        _ => {}
+      }
+      assert_eq!(xs.length(), v.len());
+      for k in 0..v.len() {
+        assert_eq!(xs.at(k), Some(v[v.len() - 1 - k]));
+      }
+    }
+  }
+
+  #[test]
+  fn shifts_against_vec() { against_vec(|i| i % 7) }
+
+  #[test]
+  fn affine_against_vec() { against_vec(|i| Affine(i % 4, i % 5)) }
+}
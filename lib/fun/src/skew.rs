@@ -31,9 +31,16 @@ struct Cell<T> {
 
 type Orc<T> = Option<Rc<Cell<T>>>;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct Skew<T>(Orc<T>);
 
+impl<T> Clone for Skew<T> {
+  #[inline]
+  fn clone(&self) -> Self {
+    Skew(self.0.clone())
+  }
+}
+
 #[inline]
 fn tip<T>(a: T) -> Tree<T> {
   Rc::new(Node(a, None, None))
@@ -77,7 +84,7 @@ fn at_tree<T>(mut k: usize, mut ts: usize, mut t: &Tree<T>) -> Option<&T> {
           break Some(&a);
         }
         k -= 1;
-        if k <= ts {
+        if k < ts {
           match ml {
             Some(lt) => t = lt,
             None => break None,
@@ -313,4 +320,18 @@ mod tests {
     assert_ne!(cons(1, cons(2, nil())), skew![1]);
     assert_eq!(skew![4, 5, 6], skew![1, 2, 3, 4, 5, 6].drop(3))
   }
+
+  #[test]
+  fn at_reaches_every_element() {
+    for n in 1..40 {
+      let mut xs = nil();
+      for i in 0..n {
+        xs = cons(i, xs);
+      }
+      for k in 0..n {
+        assert_eq!(xs.at(k), Some(&(n - 1 - k)));
+      }
+      assert_eq!(xs.at(n), None);
+    }
+  }
 }
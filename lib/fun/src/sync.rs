@@ -1,5 +1,5 @@
 cfg_if::cfg_if! {
-  if #[cfg(parallel)] {
+  if #[cfg(feature = "parallel")] {
      pub use std::sync::Arc as Lrc;
      pub use std::sync::Weak as Weak;
   } else {
@@ -0,0 +1,322 @@
+// arena hash consing
+//
+// an Arena hands out dense indices in the order terms are first interned, so unlike
+// Hc::id they are the same from run to run given the same input, and can be used as keys
+// in salsa queries or written to disk. nothing is reference counted. instead, everything
+// interned since a Mark can be freed at once, and the freed indices get a new generation
+// so stale ids are caught rather than silently aliasing whatever is interned next.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt::{self, Debug, Formatter};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::marker::PhantomData;
+use std::mem;
+use std::num::NonZeroU32;
+use std::ops::Index;
+
+use stats::{self, Stats};
+
+// the index is stored complemented, as in Meta, so Option<Id<T>> is no larger than Id<T>
+#[repr(C)]
+pub struct Id<T> {
+    index: NonZeroU32,
+    generation: u32,
+    phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> Id<T> {
+    #[inline]
+    pub fn from_parts(index: u32, generation: u32) -> Option<Self> {
+        Some(Id {
+            index: NonZeroU32::new(!index)?,
+            generation,
+            phantom: PhantomData,
+        })
+    }
+    #[inline]
+    pub fn index(self) -> u32 {
+        !self.index.get()
+    }
+    #[inline]
+    pub fn generation(self) -> u32 {
+        self.generation
+    }
+}
+
+// the derives would demand T: Copy and so on
+
+impl<T> Copy for Id<T> {}
+
+impl<T> Clone for Id<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> PartialEq for Id<T> {
+    #[inline]
+    fn eq(&self, rhs: &Self) -> bool {
+        self.index == rhs.index && self.generation == rhs.generation
+    }
+}
+impl<T> Eq for Id<T> {}
+
+impl<T> Hash for Id<T> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index().hash(state);
+        self.generation.hash(state)
+    }
+}
+
+impl<T> PartialOrd for Id<T> {
+    #[inline]
+    fn partial_cmp(&self, rhs: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(rhs))
+    }
+}
+
+// by index, so terms compare in the order they were interned
+impl<T> Ord for Id<T> {
+    #[inline]
+    fn cmp(&self, rhs: &Self) -> std::cmp::Ordering {
+        (self.index(), self.generation).cmp(&(rhs.index(), rhs.generation))
+    }
+}
+
+impl<T> Debug for Id<T> {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        if self.generation == 0 {
+            write!(fmt, "#{}", self.index())
+        } else {
+            write!(fmt, "#{}.{}", self.index(), self.generation)
+        }
+    }
+}
+
+// everything interned after a mark is freed when it is released
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Mark(u32);
+
+pub struct Arena<T: Hash + Eq + Clone, S = RandomState> {
+    values: Vec<T>,
+    // one per index ever used, so released indices remember how often they were reused
+    generations: Vec<u32>,
+    table: HashMap<T, u32, S>,
+    hits: u64,
+    misses: u64,
+    releases: u64,
+}
+
+impl<T: Hash + Eq + Clone> Arena<T, RandomState> {
+    #[inline]
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
+impl<T: Hash + Eq + Clone> Default for Arena<T, RandomState> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Hash + Eq + Clone, S: BuildHasher> Arena<T, S> {
+    #[inline]
+    pub fn with_hasher(build_hasher: S) -> Self {
+        Self::with_capacity_and_hasher(0, build_hasher)
+    }
+
+    pub fn with_capacity_and_hasher(capacity: usize, build_hasher: S) -> Self {
+        Arena {
+            values: Vec::with_capacity(capacity),
+            generations: Vec::with_capacity(capacity),
+            table: HashMap::with_capacity_and_hasher(capacity, build_hasher),
+            hits: 0,
+            misses: 0,
+            releases: 0,
+        }
+    }
+
+    #[inline]
+    fn id(&self, index: u32) -> Id<T> {
+        Id::from_parts(index, self.generations[index as usize]).unwrap()
+    }
+
+    pub fn mk_is_new(&mut self, e: T) -> (Id<T>, bool) {
+        if let Some(&index) = self.table.get(&e) {
+            self.hits += 1;
+            return (self.id(index), false);
+        }
+        self.misses += 1;
+        // u32::MAX is the niche
+        let index = u32::try_from(self.values.len()).ok().filter(|&i| i != u32::MAX).expect("arena is full");
+        if self.generations.len() == self.values.len() {
+            self.generations.push(0)
+        }
+        self.values.push(e.clone());
+        self.table.insert(e, index);
+        (self.id(index), true)
+    }
+
+    #[inline]
+    pub fn mk(&mut self, e: T) -> Id<T> {
+        self.mk_is_new(e).0
+    }
+
+    // None if the id has been released
+    #[inline]
+    pub fn get(&self, id: Id<T>) -> Option<&T> {
+        let index = id.index() as usize;
+        if index < self.values.len() && self.generations[index] == id.generation {
+            Some(&self.values[index])
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    pub fn contains(&self, id: Id<T>) -> bool {
+        self.get(id).is_some()
+    }
+
+    // the id of e if it has already been interned
+    pub fn find(&self, e: &T) -> Option<Id<T>> {
+        Some(self.id(*self.table.get(e)?))
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    #[inline]
+    pub fn mark(&self) -> Mark {
+        Mark(self.values.len() as u32)
+    }
+
+    // frees everything interned since the mark. marks should be released in the reverse of
+    // the order they were taken: releasing an older one also frees the newer ones, after
+    // which releasing those does nothing
+    pub fn release(&mut self, mark: Mark) {
+        let start = mark.0 as usize;
+        if start >= self.values.len() {
+            return;
+        }
+        for e in self.values.drain(start..) {
+            self.table.remove(&e);
+        }
+        for generation in &mut self.generations[start..] {
+            *generation = generation.wrapping_add(1)
+        }
+        self.releases += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.release(Mark(0))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Id<T>, &T)> + '_ {
+        self.values.iter().enumerate().map(move |(i, e)| (self.id(i as u32), e))
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            live: self.values.len(),
+            dead: 0,
+            hits: self.hits,
+            misses: self.misses,
+            collections: self.releases,
+            bytes: self.values.capacity() * mem::size_of::<T>()
+                + self.generations.capacity() * mem::size_of::<u32>()
+                + stats::entry_bytes::<T, u32>(self.table.capacity()),
+        }
+    }
+}
+
+impl<T: Hash + Eq + Clone, S: BuildHasher> Index<Id<T>> for Arena<T, S> {
+    type Output = T;
+    fn index(&self, id: Id<T>) -> &T {
+        match self.get(id) {
+            Some(e) => e,
+            None => panic!("stale arena id {:?}", id),
+        }
+    }
+}
+
+impl<T: Hash + Debug + Eq + Clone, S: BuildHasher> Debug for Arena<T, S> {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "arena:")?;
+        for (id, e) in self.iter() {
+            write!(fmt, "\n  | {:?} = {:?}", id, e)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, PartialEq, Eq, Hash, Debug)]
+    enum Term {
+        Var(u32),
+        App(Id<Term>, Id<Term>),
+    }
+
+    fn spine(arena: &mut Arena<Term>, n: u32) -> Id<Term> {
+        let mut t = arena.mk(Term::Var(0));
+        for i in 1..n {
+            let v = arena.mk(Term::Var(i % 5));
+            t = arena.mk(Term::App(t, v));
+        }
+        t
+    }
+
+    #[test]
+    fn deterministic() {
+        assert_eq!(mem::size_of::<Option<Id<Term>>>(), mem::size_of::<Id<Term>>());
+        let mut a = Arena::new();
+        let mut b = Arena::new();
+        assert_eq!(spine(&mut a, 100), spine(&mut b, 100));
+        let contents = |arena: &Arena<Term>| arena.iter().map(|(id, e)| (id, e.clone())).collect::<Vec<_>>();
+        assert_eq!(contents(&a), contents(&b));
+        let t = spine(&mut a, 100);
+        assert_eq!(t.index() as usize, a.len() - 1);
+        assert!(!a.mk_is_new(Term::Var(3)).1);
+        assert_eq!(a.find(&Term::Var(4)).map(Id::index), Some(7));
+    }
+
+    #[test]
+    fn regions() {
+        let mut arena = Arena::new();
+        let x = arena.mk(Term::Var(0));
+        let mark = arena.mark();
+        let t = spine(&mut arena, 10);
+        let y = arena.mk(Term::Var(7));
+        assert!(matches!(arena[t], Term::App(_, v) if arena[v] == Term::Var(4)));
+        arena.release(mark);
+        assert_eq!(arena.len(), 1);
+        assert!(arena.contains(x));
+        assert!(!arena.contains(t));
+        assert_eq!(arena.get(y), None);
+        // the freed indices are handed out again, with a new generation
+        let y2 = arena.mk(Term::Var(7));
+        assert_eq!((y2.index(), y2.generation()), (1, 1));
+        assert_ne!(y, y2);
+        assert_eq!(arena[y2], Term::Var(7));
+        assert_eq!(arena.stats().collections, 1);
+    }
+}
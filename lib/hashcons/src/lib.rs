@@ -1,25 +1,97 @@
+#![feature(strict_provenance)]
+
 // the api is inspired by the rust `hashconsing` library, but
 // here we take pains to keep the structure null-pointer optimizable
 // for immutable structures: replace Rc<T> with Hc<T> and allocate with
 // a Constable and your code should be more or less as before with
 // increased sharing.
 
+#[cfg(feature = "parallel")]
+extern crate hashbrown;
+extern crate serde;
+#[cfg(test)]
+extern crate serde_json;
+
 use std::borrow::Borrow;
+use std::cmp::Ordering;
 use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
 use std::fmt::{self, Debug, Display, Formatter};
 use std::hash::{BuildHasher, Hash, Hasher};
+use std::mem;
 use std::ops::Deref;
 use std::rc::{Rc, Weak};
+use std::sync::atomic::{self, AtomicU64};
+
+pub mod arena;
+pub mod persist;
+pub mod stats;
+#[cfg(feature = "parallel")]
+pub mod sync;
+
+pub use arena::{Arena, Id, Mark};
+pub use stats::{totals, Stats};
+#[cfg(feature = "parallel")]
+pub use sync::{SyncConstable, SyncHc, SyncWeakHc};
+
+// the value along with its structural hash, so hashing a term that contains Hc children
+// only has to look at the top node, and a serial number recording allocation order
+struct HcBox<T: ?Sized> {
+    hash: u64,
+    serial: u64,
+    value: T,
+}
+
+static NEXT_SERIAL: AtomicU64 = AtomicU64::new(0);
+
+impl<T> HcBox<T> {
+    #[inline]
+    fn new(hash: u64, value: T) -> Self {
+        stats::allocated(Self::FOOTPRINT);
+        HcBox {
+            hash,
+            serial: NEXT_SERIAL.fetch_add(1, atomic::Ordering::Relaxed),
+            value,
+        }
+    }
+
+    // including the reference counts of the Rc or Arc around it
+    const FOOTPRINT: usize = mem::size_of::<Self>() + 2 * mem::size_of::<usize>();
+}
+
+impl<T: ?Sized> Drop for HcBox<T> {
+    fn drop(&mut self) {
+        stats::freed(mem::size_of_val(self) + 2 * mem::size_of::<usize>())
+    }
+}
+
+// tables sweep out dead entries once they reach this many entries, and afterwards once they
+// double the number of live ones, so sweeping is amortized O(1) per mk
+const MIN_THRESHOLD: usize = 64;
+
+#[inline]
+fn next_threshold(live: usize) -> usize {
+    MIN_THRESHOLD.max(2 * live)
+}
 
 // null pointer optimization
 #[repr(transparent)]
-pub struct Hc<T: ?Sized>(Rc<T>);
+pub struct Hc<T: ?Sized>(Rc<HcBox<T>>);
 
 impl<T> Hc<T> {
     #[inline]
     pub fn get(&self) -> &T {
-        self.0.borrow()
+        &self.0.value
+    }
+    // the hash of the value computed by the Constable that made it
+    #[inline]
+    pub fn hash_code(&self) -> u64 {
+        self.0.hash
+    }
+    // handles made earlier compare less
+    #[inline]
+    pub fn serial(&self) -> u64 {
+        self.0.serial
     }
     #[inline]
     pub fn id(&self) -> usize {
@@ -38,14 +110,14 @@ impl<T> Hc<T> {
 impl<T> Borrow<T> for Hc<T> {
     #[inline]
     fn borrow(&self) -> &T {
-        self.0.borrow()
+        &self.0.value
     }
 }
 
 impl<T> AsRef<T> for Hc<T> {
     #[inline]
     fn as_ref(&self) -> &T {
-        self.0.borrow()
+        &self.0.value
     }
 }
 
@@ -53,20 +125,20 @@ impl<T> Deref for Hc<T> {
     type Target = T;
     #[inline]
     fn deref(&self) -> &T {
-        self.0.deref()
+        &self.0.value
     }
 }
 
 impl<T: Display> Display for Hc<T> {
     #[inline]
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
-        self.0.fmt(fmt)
+        self.0.value.fmt(fmt)
     }
 }
 
 impl<T: Debug> Debug for Hc<T> {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
-        write!(fmt, "{:?}", self.0)
+        write!(fmt, "{:?}", self.0.value)
     }
 }
 
@@ -85,10 +157,32 @@ impl<T> PartialEq for Hc<T> {
 }
 impl<T> Eq for Hc<T> {}
 
+// equal handles share an allocation, so they share a cached hash
+impl<T> Hash for Hc<T> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0.hash)
+    }
+}
+
+impl<T> PartialOrd for Hc<T> {
+    #[inline]
+    fn partial_cmp(&self, rhs: &Self) -> Option<Ordering> {
+        Some(self.cmp(rhs))
+    }
+}
+
+impl<T> Ord for Hc<T> {
+    #[inline]
+    fn cmp(&self, rhs: &Self) -> Ordering {
+        self.0.serial.cmp(&rhs.0.serial)
+    }
+}
+
 // weak reference to a hash consed structure
 // null pointer optimization
 #[repr(transparent)]
-pub struct WeakHc<T: ?Sized>(Weak<T>);
+pub struct WeakHc<T: ?Sized>(Weak<HcBox<T>>);
 
 impl<T> WeakHc<T> {
     #[inline]
@@ -104,7 +198,7 @@ impl<T> WeakHc<T> {
 impl<T: Debug> Debug for WeakHc<T> {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
         match self.0.upgrade() {
-            Some(r) => r.fmt(fmt),
+            Some(r) => r.value.fmt(fmt),
             None => write!(fmt, "<removed>"),
         }
     }
@@ -114,7 +208,7 @@ impl<T: Display> Display for WeakHc<T> {
     #[inline]
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
         match self.0.upgrade() {
-            Some(r) => r.fmt(fmt),
+            Some(r) => r.value.fmt(fmt),
             None => write!(fmt, "<removed>"),
         }
     }
@@ -135,28 +229,40 @@ impl<T> PartialEq for WeakHc<T> {
 }
 impl<T> Eq for WeakHc<T> {}
 
-pub struct Constable<T: Hash + Eq + Clone, S = RandomState>(HashMap<T, WeakHc<T>, S>);
+pub struct Constable<T: Hash + Eq + Clone, S = RandomState> {
+    table: HashMap<T, WeakHc<T>, S>,
+    threshold: usize,
+    hits: u64,
+    misses: u64,
+    collections: u64,
+}
 
 impl<T: Hash + Eq + Clone> Constable<T, RandomState> {
     #[inline]
     pub fn new() -> Self {
-        Constable(HashMap::new())
+        Self::with_hasher(RandomState::new())
     }
     #[inline]
     pub fn with_capacity(capacity: usize) -> Self {
-        Constable(HashMap::with_capacity(capacity))
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
     }
 }
 
 impl<T: Hash + Eq + Clone, S: BuildHasher> Constable<T, S> {
     #[inline]
     pub fn with_hasher(build_hasher: S) -> Self {
-        Constable(HashMap::with_hasher(build_hasher))
+        Self::with_capacity_and_hasher(0, build_hasher)
     }
 
     #[inline]
     pub fn with_capacity_and_hasher(capacity: usize, build_hasher: S) -> Self {
-        Constable(HashMap::with_capacity_and_hasher(capacity, build_hasher))
+        Constable {
+            table: HashMap::with_capacity_and_hasher(capacity, build_hasher),
+            threshold: next_threshold(capacity),
+            hits: 0,
+            misses: 0,
+            collections: 0,
+        }
     }
 
     /// One of the following must hold:
@@ -167,7 +273,10 @@ impl<T: Hash + Eq + Clone, S: BuildHasher> Constable<T, S> {
     /// This is checked in `debug` but not `release`.
     #[inline]
     fn insert(&mut self, key: T, wc: WeakHc<T>) {
-        let prev = self.0.insert(key, wc);
+        if self.table.len() >= self.threshold {
+            self.sweep()
+        }
+        let prev = self.table.insert(key, wc);
         debug_assert!(match prev {
             None => true,
             Some(prev) => prev.upgrade().is_none(),
@@ -177,14 +286,50 @@ impl<T: Hash + Eq + Clone, S: BuildHasher> Constable<T, S> {
     /// Attempts to retrieve an *upgradable* value from the map.
     #[inline]
     fn get(&self, key: &T) -> Option<Hc<T>> {
-        self.0.get(key)?.upgrade()
+        self.table.get(key)?.upgrade()
+    }
+
+    fn sweep(&mut self) {
+        self.table.retain(|_, value| value.0.strong_count() > 0);
+        self.threshold = next_threshold(self.table.len());
+        self.collections += 1;
+        stats::collected();
+    }
+
+    // the handle for e if it has already been interned and is still alive
+    #[inline]
+    pub fn find(&self, e: &T) -> Option<Hc<T>> {
+        self.get(e)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    // walks the whole table to tell live entries from dead ones
+    pub fn stats(&self) -> Stats {
+        let live = self.table.values().filter(|value| value.0.strong_count() > 0).count();
+        Stats {
+            live,
+            dead: self.table.len() - live,
+            hits: self.hits,
+            misses: self.misses,
+            collections: self.collections,
+            bytes: stats::entry_bytes::<T, WeakHc<T>>(self.table.capacity()) + live * HcBox::<T>::FOOTPRINT,
+        }
     }
 }
 
 impl<T: Hash + Display + Eq + Clone, S> Display for Constable<T, S> {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
         write!(fmt, "constable:")?;
-        for e in self.0.values() {
+        for e in self.table.values() {
             write!(fmt, "\n  | {}", e)?;
         }
         Ok(())
@@ -194,7 +339,7 @@ impl<T: Hash + Display + Eq + Clone, S> Display for Constable<T, S> {
 impl<T: Hash + Debug + Eq + Clone, S> Debug for Constable<T, S> {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
         write!(fmt, "constable:")?;
-        for e in self.0.values() {
+        for e in self.table.values() {
             write!(fmt, "\n  | {:?}", e)?;
         }
         Ok(())
@@ -202,8 +347,10 @@ impl<T: Hash + Debug + Eq + Clone, S> Debug for Constable<T, S> {
 }
 
 pub trait HashConstable<T: Hash>: Sized {
-    fn mk_is_new(self, elm: T) -> (Hc<T>, bool);
-    fn mk(self, elm: T) -> Hc<T> {
+    // Hc<T> for a Constable, SyncHc<T> for a SyncConstable
+    type Handle;
+    fn mk_is_new(self, elm: T) -> (Self::Handle, bool);
+    fn mk(self, elm: T) -> Self::Handle {
         self.mk_is_new(elm).0
     }
     fn collect(self);
@@ -213,23 +360,31 @@ pub trait HashConstable<T: Hash>: Sized {
 }
 
 impl<'a, T: Hash + Eq + Clone, S: BuildHasher> HashConstable<T> for &'a mut Constable<T, S> {
+    type Handle = Hc<T>;
+
     fn mk_is_new(self, e: T) -> (Hc<T>, bool) {
         // If the element is known and upgradable return it.
         if let Some(hc) = self.get(&e) {
-            debug_assert!(*hc.0 == e);
+            debug_assert!(*hc == e);
+            self.hits += 1;
+            stats::hit();
             return (hc.clone(), false); // add a reference
         }
-        let hc = Hc(Rc::new(e.clone()));
+        self.misses += 1;
+        stats::miss();
+        // children of e are hashed by their cached hashes, so this only walks the top node
+        let hash = self.table.hasher().hash_one(&e);
+        let hc = Hc(Rc::new(HcBox::new(hash, e.clone())));
         self.insert(e, hc.downgrade());
         (hc, true)
     }
 
     fn collect(self) {
-        self.0.retain(|_, value| value.0.strong_count() > 0)
+        self.sweep()
     }
 
     fn shrink_to_fit(self) {
-        self.0.shrink_to_fit()
+        self.table.shrink_to_fit()
     }
 
     fn collect_to_fit(self) {
@@ -238,6 +393,106 @@ impl<'a, T: Hash + Eq + Clone, S: BuildHasher> HashConstable<T> for &'a mut Cons
     }
 
     fn reserve(self, additional: usize) {
-        self.0.reserve(additional)
+        self.table.reserve(additional)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::BTreeSet;
+    use std::sync::atomic::AtomicUsize;
+
+    #[derive(Clone, PartialEq, Eq, Hash, Debug)]
+    enum Term {
+        Var(u32),
+        App(Hc<Term>, Hc<Term>),
+    }
+
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+
+    // counts the bytes fed to every hasher it builds
+    #[derive(Default, Clone)]
+    struct Counting;
+    struct CountingHasher(DefaultHasher);
+
+    impl BuildHasher for Counting {
+        type Hasher = CountingHasher;
+        fn build_hasher(&self) -> CountingHasher {
+            CountingHasher(DefaultHasher::new())
+        }
+    }
+
+    impl Hasher for CountingHasher {
+        fn finish(&self) -> u64 {
+            self.0.finish()
+        }
+        fn write(&mut self, bytes: &[u8]) {
+            WRITES.fetch_add(bytes.len(), atomic::Ordering::Relaxed);
+            self.0.write(bytes)
+        }
+    }
+
+    #[test]
+    fn sweeps_automatically() {
+        let mut table = Constable::new();
+        let keep: Vec<_> = (0..10).map(|i| table.mk(Term::Var(i))).collect();
+        for i in 10..10_000 {
+            table.mk(Term::Var(i));
+        }
+        // the dropped variables never pile up past a couple of thresholds' worth
+        assert!(table.len() <= 2 * MIN_THRESHOLD, "{} entries", table.len());
+        let stats = table.stats();
+        assert_eq!(stats.live, keep.len());
+        assert_eq!(stats.live + stats.dead, table.len());
+        assert_eq!((stats.hits, stats.misses), (0, 10_000));
+        assert!(stats.collections > 0);
+        table.mk(Term::Var(3));
+        assert_eq!(table.stats().hits, 1);
+        table.collect();
+        assert_eq!(table.stats().dead, 0);
+        assert!(totals().misses >= 10_000);
+    }
+
+    fn spine(table: &mut Constable<Term, Counting>, n: u32) -> Hc<Term> {
+        let mut t = table.mk(Term::Var(0));
+        for i in 1..n {
+            let v = table.mk(Term::Var(i));
+            t = table.mk(Term::App(t, v));
+        }
+        t
+    }
+
+    #[test]
+    fn hashing_is_shallow() {
+        let mut table = Constable::with_hasher(Counting);
+        let before = WRITES.load(atomic::Ordering::Relaxed);
+        let t = spine(&mut table, 1000);
+        let during = WRITES.load(atomic::Ordering::Relaxed) - before;
+        // each mk hashes a node a few times, at a couple of dozen bytes a go. a deep hash
+        // would instead feed the hasher ~n^2/2 variables, several megabytes
+        assert!(during < 1000 * 256, "{} bytes hashed", during);
+        assert_eq!(t, spine(&mut table, 1000));
+    }
+
+    #[test]
+    fn ordered_by_allocation() {
+        let mut table = Constable::new();
+        let x = table.mk(Term::Var(1));
+        let y = table.mk(Term::Var(0));
+        let xy = table.mk(Term::App(x.clone(), y.clone()));
+        assert!(x < y && y < xy);
+        assert_eq!(table.mk(Term::Var(1)).cmp(&x), Ordering::Equal);
+        let set: BTreeSet<_> = vec![xy.clone(), y.clone(), x.clone(), y.clone()].into_iter().collect();
+        assert_eq!(set.into_iter().collect::<Vec<_>>(), vec![x.clone(), y, xy.clone()]);
+        let y2 = table.mk(Term::Var(0));
+        let xy2 = table.mk(Term::App(x, y2));
+        let mut h1 = DefaultHasher::new();
+        let mut h2 = DefaultHasher::new();
+        xy.hash(&mut h1);
+        xy2.hash(&mut h2);
+        assert_eq!(h1.finish(), h2.finish());
+        assert_eq!(xy.hash_code(), xy2.hash_code());
     }
 }
@@ -0,0 +1,295 @@
+// serializing hash-consed DAGs without unsharing them
+//
+// within a session, the first time a given Hc is serialized it is written out in full as
+// `Def(value)`, and it is numbered in the order the Defs start. after that, it is written as
+// `Ref(n)`. loading mirrors the numbering and re-interns each Def into a Constable, so the
+// result is shared at least as much as the input was.
+//
+// `save` and `load` run a whole value in one session. outside of one, each outermost Hc
+// gets a session of its own, which is still correct, but can't share between siblings.
+
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::mem;
+
+use serde::de::{self, EnumAccess, VariantAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use {Constable, HashConstable, Hc};
+
+struct Loading {
+    // each is an Hc<T> for some T, or None while its Def is being read
+    nodes: Vec<Option<Box<dyn Any>>>,
+    // a Constable<T> for each T seen so far
+    constables: HashMap<TypeId, Box<dyn Any>>,
+}
+
+thread_local! {
+    static SAVING: RefCell<Option<HashMap<usize, u32>>> = const { RefCell::new(None) };
+    static LOADING: RefCell<Option<Loading>> = const { RefCell::new(None) };
+}
+
+// ends the session even if serialization panics
+struct Session(fn());
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        (self.0)()
+    }
+}
+
+fn saving() -> bool {
+    SAVING.with(|s| s.borrow().is_some())
+}
+
+fn loading() -> bool {
+    LOADING.with(|s| s.borrow().is_some())
+}
+
+pub fn save<T: ?Sized + Serialize, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    if saving() {
+        return value.serialize(serializer);
+    }
+    SAVING.with(|s| *s.borrow_mut() = Some(HashMap::new()));
+    let _session = Session(|| SAVING.with(|s| *s.borrow_mut() = None));
+    value.serialize(serializer)
+}
+
+// interns into fresh tables
+pub fn load<'de, T: Deserialize<'de>, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+    if loading() {
+        return T::deserialize(deserializer);
+    }
+    LOADING.with(|s| {
+        *s.borrow_mut() = Some(Loading {
+            nodes: Vec::new(),
+            constables: HashMap::new(),
+        })
+    });
+    let _session = Session(|| LOADING.with(|s| *s.borrow_mut() = None));
+    T::deserialize(deserializer)
+}
+
+// interns every Hc<U> into `constable`, so anything already in there is shared with the
+// result. Hcs of other types go into fresh tables.
+pub fn load_into<'de, T, U, D>(constable: &mut Constable<U>, deserializer: D) -> Result<T, D::Error> where
+    T: Deserialize<'de>,
+    U: Hash + Eq + Clone + 'static,
+    D: Deserializer<'de>,
+{
+    if loading() {
+        return Err(de::Error::custom("load_into can't be nested inside another load"));
+    }
+    let mut constables: HashMap<TypeId, Box<dyn Any>> = HashMap::new();
+    constables.insert(TypeId::of::<U>(), Box::new(mem::replace(constable, Constable::new())));
+    LOADING.with(|s| {
+        *s.borrow_mut() = Some(Loading {
+            nodes: Vec::new(),
+            constables,
+        })
+    });
+    let _session = Lent(constable);
+    T::deserialize(deserializer)
+}
+
+// ends a load_into session, handing the caller's table back even if deserialization panics
+struct Lent<'a, U: Hash + Eq + Clone + 'static>(&'a mut Constable<U>);
+
+impl<'a, U: Hash + Eq + Clone + 'static> Drop for Lent<'a, U> {
+    fn drop(&mut self) {
+        let loading = LOADING.with(|s| s.borrow_mut().take());
+        if let Some(table) = loading.and_then(|mut l| l.constables.remove(&TypeId::of::<U>())) {
+            *self.0 = *table.downcast::<Constable<U>>().unwrap();
+        }
+    }
+}
+
+impl<T: Serialize> Serialize for Hc<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !saving() {
+            return save(self, serializer);
+        }
+        let seen = SAVING.with(|s| {
+            let mut s = s.borrow_mut();
+            let table = s.as_mut().unwrap();
+            match table.get(&self.id()) {
+                Some(&n) => Some(n),
+                None => {
+                    let n = table.len() as u32;
+                    table.insert(self.id(), n);
+                    None
+                }
+            }
+        });
+        match seen {
+            Some(n) => serializer.serialize_newtype_variant("Shared", 1, "Ref", &n),
+            None => serializer.serialize_newtype_variant("Shared", 0, "Def", self.get()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+enum Tag {
+    Def,
+    Ref,
+}
+
+struct SharedVisitor<T>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de> + Hash + Eq + Clone + 'static> Visitor<'de> for SharedVisitor<T> {
+    type Value = Hc<T>;
+
+    fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "a hash-consed value")
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Hc<T>, A::Error> {
+        match data.variant()? {
+            (Tag::Def, variant) => {
+                let n = LOADING.with(|s| {
+                    let mut s = s.borrow_mut();
+                    let nodes = &mut s.as_mut().unwrap().nodes;
+                    nodes.push(None);
+                    nodes.len() - 1
+                });
+                // children are read, and numbered, in here
+                let value: T = variant.newtype_variant()?;
+                LOADING.with(|s| {
+                    let mut s = s.borrow_mut();
+                    let loading = s.as_mut().unwrap();
+                    let constable = loading
+                        .constables
+                        .entry(TypeId::of::<T>())
+                        .or_insert_with(|| Box::new(Constable::<T>::new()))
+                        .downcast_mut::<Constable<T>>()
+                        .unwrap();
+                    let hc = constable.mk(value);
+                    loading.nodes[n] = Some(Box::new(hc.clone()));
+                    Ok(hc)
+                })
+            }
+            (Tag::Ref, variant) => {
+                let n: u32 = variant.newtype_variant()?;
+                LOADING.with(|s| {
+                    let s = s.borrow();
+                    match s.as_ref().unwrap().nodes.get(n as usize) {
+                        None => Err(de::Error::custom(format_args!("reference to undefined node {}", n))),
+                        Some(None) => Err(de::Error::custom(format_args!("node {} refers to itself", n))),
+                        Some(Some(node)) => match node.downcast_ref::<Hc<T>>() {
+                            Some(hc) => Ok(hc.clone()),
+                            None => Err(de::Error::custom(format_args!("node {} has the wrong type", n))),
+                        },
+                    }
+                })
+            }
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de> + Hash + Eq + Clone + 'static> Deserialize<'de> for Hc<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if !loading() {
+            return load(deserializer);
+        }
+        deserializer.deserialize_enum("Shared", &["Def", "Ref"], SharedVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+    use std::panic::{self, AssertUnwindSafe};
+
+    #[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+    enum Term {
+        Var(u32),
+        App(Hc<Term>, Hc<Term>),
+    }
+
+    // 2^n leaves, n+1 distinct nodes
+    fn tower(table: &mut Constable<Term>, n: u32) -> Hc<Term> {
+        let mut t = table.mk(Term::Var(0));
+        for _ in 0..n {
+            t = table.mk(Term::App(t.clone(), t));
+        }
+        t
+    }
+
+    fn depth(t: &Hc<Term>) -> u32 {
+        match t.get() {
+            Term::Var(_) => 0,
+            Term::App(f, x) => {
+                assert_eq!(f, x);
+                1 + depth(f)
+            }
+        }
+    }
+
+    #[test]
+    fn preserves_sharing() {
+        let mut table = Constable::new();
+        let t = tower(&mut table, 40);
+        let json = serde_json::to_string(&t).unwrap();
+        assert!(json.len() < 40 * 40, "{}", json);
+        let loaded: Hc<Term> = serde_json::from_str(&json).unwrap();
+        assert_eq!(depth(&loaded), 40);
+        // a fresh table, so a fresh copy
+        assert_ne!(loaded, t);
+        let mut de = serde_json::Deserializer::from_str(&json);
+        let reloaded: Hc<Term> = load_into(&mut table, &mut de).unwrap();
+        assert_eq!(reloaded, t);
+    }
+
+    #[test]
+    fn sessions() {
+        let mut table = Constable::new();
+        let t = tower(&mut table, 3);
+        let ts = vec![t.clone(), t.clone(), table.mk(Term::Var(1))];
+        // each element is saved on its own
+        let separate = serde_json::to_string(&ts).unwrap();
+        let mut out = Vec::new();
+        save(&ts, &mut serde_json::Serializer::new(&mut out)).unwrap();
+        let shared = String::from_utf8(out).unwrap();
+        assert!(shared.len() < separate.len());
+        for json in &[separate, shared] {
+            let mut de = serde_json::Deserializer::from_str(json);
+            let loaded: Vec<Hc<Term>> = load_into(&mut table, &mut de).unwrap();
+            assert_eq!(loaded, ts);
+        }
+        let bad = serde_json::from_str::<Hc<Term>>(r#"{"Def":{"App":[{"Ref":0},{"Ref":0}]}}"#);
+        assert!(bad.unwrap_err().to_string().contains("refers to itself"));
+        assert!(serde_json::from_str::<Hc<Term>>(r#"{"Ref":3}"#).is_err());
+    }
+
+    #[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize)]
+    struct Boom;
+
+    impl<'de> Deserialize<'de> for Boom {
+        fn deserialize<D: Deserializer<'de>>(_: D) -> Result<Self, D::Error> {
+            panic!("boom")
+        }
+    }
+
+    #[test]
+    fn panics() {
+        let mut table = Constable::new();
+        let t = tower(&mut table, 3);
+        let json = serde_json::to_string(&(t.clone(), Boom)).unwrap();
+        let caught = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut de = serde_json::Deserializer::from_str(&json);
+            load_into::<(Hc<Term>, Boom), _, _>(&mut table, &mut de)
+        }));
+        assert!(caught.is_err());
+        // the table came back, with everything it had in it
+        assert_eq!(table.len(), 4);
+        assert_eq!(tower(&mut table, 3), t);
+        let mut de = serde_json::Deserializer::from_str(&json[..json.rfind(',').unwrap()]);
+        assert!(load_into::<(Hc<Term>, Boom), _, _>(&mut table, &mut de).is_err());
+        assert!(!loading());
+    }
+}
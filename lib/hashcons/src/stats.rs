@@ -0,0 +1,198 @@
+// table statistics, for tuning how much sharing we get
+
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+use std::ops::{Add, AddAssign};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+// a snapshot of one table
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Stats {
+    // entries whose handle is still alive
+    pub live: usize,
+    // entries whose handle has been dropped, awaiting collection
+    pub dead: usize,
+    // mk calls that found an existing handle
+    pub hits: u64,
+    // mk calls that allocated a new handle
+    pub misses: u64,
+    // times dead entries were swept, by hand or automatically
+    pub collections: u64,
+    // approximate heap usage of the table and its live handles
+    pub bytes: usize,
+}
+
+impl Stats {
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+impl Add for Stats {
+    type Output = Stats;
+    fn add(mut self, rhs: Stats) -> Stats {
+        self += rhs;
+        self
+    }
+}
+
+impl AddAssign for Stats {
+    fn add_assign(&mut self, rhs: Stats) {
+        self.live += rhs.live;
+        self.dead += rhs.dead;
+        self.hits += rhs.hits;
+        self.misses += rhs.misses;
+        self.collections += rhs.collections;
+        self.bytes += rhs.bytes;
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{} live, {} dead, {} hits, {} misses ({:.1}% hit rate), {} collections, {} bytes",
+            self.live,
+            self.dead,
+            self.hits,
+            self.misses,
+            100.0 * self.hit_rate(),
+            self.collections,
+            self.bytes
+        )
+    }
+}
+
+// the size of a hash table entry mapping K to V, counting hashbrown's control byte
+pub(crate) fn entry_bytes<K, V>(capacity: usize) -> usize {
+    capacity * (mem::size_of::<(K, V)>() + 1)
+}
+
+// process-wide counters, summed over every table, including ones that have been dropped.
+// dead entries only make sense per table, so `totals` reports none.
+//
+// each thread counts into its own, so threads sharing a SyncConstable don't all write to
+// one cache line, and `totals` adds them up when it is asked. a handle may be freed on
+// another thread than the one that allocated it, so a thread's handle count can wrap
+// below zero, but the sum comes out right.
+#[repr(align(128))]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    collections: AtomicU64,
+    handles: AtomicUsize,
+    handle_bytes: AtomicUsize,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Counters {
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            collections: AtomicU64::new(0),
+            handles: AtomicUsize::new(0),
+            handle_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    fn add_to(&self, that: &Counters) {
+        that.hits.fetch_add(self.hits.load(Ordering::Relaxed), Ordering::Relaxed);
+        that.misses.fetch_add(self.misses.load(Ordering::Relaxed), Ordering::Relaxed);
+        that.collections.fetch_add(self.collections.load(Ordering::Relaxed), Ordering::Relaxed);
+        that.handles.fetch_add(self.handles.load(Ordering::Relaxed), Ordering::Relaxed);
+        that.handle_bytes.fetch_add(self.handle_bytes.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+}
+
+// the counters of every running thread that has counted anything
+static THREADS: Mutex<Vec<Arc<Counters>>> = Mutex::new(Vec::new());
+
+// what threads that have exited counted, and anything counted while a thread is exiting
+static RETIRED: Counters = Counters::new();
+
+struct Local(Arc<Counters>);
+
+impl Drop for Local {
+    fn drop(&mut self) {
+        let mut threads = THREADS.lock().unwrap_or_else(|e| e.into_inner());
+        threads.retain(|c| !Arc::ptr_eq(c, &self.0));
+        self.0.add_to(&RETIRED);
+    }
+}
+
+thread_local! {
+    static LOCAL: Local = {
+        let counters = Arc::new(Counters::new());
+        THREADS.lock().unwrap_or_else(|e| e.into_inner()).push(counters.clone());
+        Local(counters)
+    };
+}
+
+#[inline]
+fn count<F: Fn(&Counters)>(f: F) {
+    if LOCAL.try_with(|local| f(&local.0)).is_err() {
+        f(&RETIRED)
+    }
+}
+
+#[inline]
+pub(crate) fn hit() {
+    count(|c| {
+        c.hits.fetch_add(1, Ordering::Relaxed);
+    })
+}
+
+#[inline]
+pub(crate) fn miss() {
+    count(|c| {
+        c.misses.fetch_add(1, Ordering::Relaxed);
+    })
+}
+
+#[inline]
+pub(crate) fn collected() {
+    count(|c| {
+        c.collections.fetch_add(1, Ordering::Relaxed);
+    })
+}
+
+#[inline]
+pub(crate) fn allocated(bytes: usize) {
+    count(|c| {
+        c.handles.fetch_add(1, Ordering::Relaxed);
+        c.handle_bytes.fetch_add(bytes, Ordering::Relaxed);
+    })
+}
+
+#[inline]
+pub(crate) fn freed(bytes: usize) {
+    count(|c| {
+        c.handles.fetch_sub(1, Ordering::Relaxed);
+        c.handle_bytes.fetch_sub(bytes, Ordering::Relaxed);
+    })
+}
+
+// `live` and `bytes` count the handles alive right now, not the tables holding them
+pub fn totals() -> Stats {
+    let sum = Counters::new();
+    // under the lock, so an exiting thread's counts are in one place or the other
+    let threads = THREADS.lock().unwrap_or_else(|e| e.into_inner());
+    RETIRED.add_to(&sum);
+    for c in threads.iter() {
+        c.add_to(&sum)
+    }
+    Stats {
+        live: sum.handles.into_inner(),
+        dead: 0,
+        hits: sum.hits.into_inner(),
+        misses: sum.misses.into_inner(),
+        collections: sum.collections.into_inner(),
+        bytes: sum.handle_bytes.into_inner(),
+    }
+}
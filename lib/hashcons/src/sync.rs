@@ -0,0 +1,501 @@
+// thread-safe hash consing
+//
+// SyncHc is to Hc as Arc is to Rc. a SyncConstable splits its table into shards, each
+// behind its own lock, picked by the hash of the key, so threads interning unrelated
+// terms rarely contend.
+
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::thread;
+
+use hashbrown::hash_map::RawEntryMut;
+use hashbrown::HashMap;
+
+use stats::{self, Stats};
+use {next_threshold, HashConstable, HcBox};
+
+// null pointer optimization
+#[repr(transparent)]
+pub struct SyncHc<T: ?Sized>(Arc<HcBox<T>>);
+
+impl<T> SyncHc<T> {
+    #[inline]
+    pub fn get(&self) -> &T {
+        &self.0.value
+    }
+    #[inline]
+    pub fn hash_code(&self) -> u64 {
+        self.0.hash
+    }
+    #[inline]
+    pub fn serial(&self) -> u64 {
+        self.0.serial
+    }
+    #[inline]
+    pub fn id(&self) -> usize {
+        Arc::as_ptr(&self.0).addr()
+    }
+    #[inline]
+    pub fn downgrade(&self) -> SyncWeakHc<T> {
+        SyncWeakHc(Arc::downgrade(&self.0))
+    }
+    #[inline]
+    pub fn strong_count(&self) -> usize {
+        Arc::strong_count(&self.0)
+    }
+}
+
+impl<T> Borrow<T> for SyncHc<T> {
+    #[inline]
+    fn borrow(&self) -> &T {
+        &self.0.value
+    }
+}
+
+impl<T> AsRef<T> for SyncHc<T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        &self.0.value
+    }
+}
+
+impl<T> Deref for SyncHc<T> {
+    type Target = T;
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.0.value
+    }
+}
+
+impl<T: Display> Display for SyncHc<T> {
+    #[inline]
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        self.0.value.fmt(fmt)
+    }
+}
+
+impl<T: Debug> Debug for SyncHc<T> {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "{:?}", self.0.value)
+    }
+}
+
+impl<T> Clone for SyncHc<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        SyncHc(self.0.clone())
+    }
+}
+
+impl<T> PartialEq for SyncHc<T> {
+    #[inline]
+    fn eq(&self, rhs: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &rhs.0)
+    }
+}
+impl<T> Eq for SyncHc<T> {}
+
+impl<T> Hash for SyncHc<T> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0.hash)
+    }
+}
+
+impl<T> PartialOrd for SyncHc<T> {
+    #[inline]
+    fn partial_cmp(&self, rhs: &Self) -> Option<Ordering> {
+        Some(self.cmp(rhs))
+    }
+}
+
+impl<T> Ord for SyncHc<T> {
+    #[inline]
+    fn cmp(&self, rhs: &Self) -> Ordering {
+        self.0.serial.cmp(&rhs.0.serial)
+    }
+}
+
+// weak reference to a hash consed structure
+// null pointer optimization
+#[repr(transparent)]
+pub struct SyncWeakHc<T: ?Sized>(Weak<HcBox<T>>);
+
+impl<T> SyncWeakHc<T> {
+    #[inline]
+    pub fn upgrade(&self) -> Option<SyncHc<T>> {
+        Some(SyncHc(self.0.upgrade()?))
+    }
+    #[inline]
+    pub fn id(&self) -> usize {
+        Weak::as_ptr(&self.0) as *const () as usize
+    }
+}
+
+impl<T: Debug> Debug for SyncWeakHc<T> {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self.0.upgrade() {
+            Some(r) => r.value.fmt(fmt),
+            None => write!(fmt, "<removed>"),
+        }
+    }
+}
+
+impl<T: Display> Display for SyncWeakHc<T> {
+    #[inline]
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self.0.upgrade() {
+            Some(r) => r.value.fmt(fmt),
+            None => write!(fmt, "<removed>"),
+        }
+    }
+}
+
+impl<T> Hash for SyncWeakHc<T> {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id().hash(state)
+    }
+}
+
+impl<T> PartialEq for SyncWeakHc<T> {
+    #[inline]
+    fn eq(&self, rhs: &Self) -> bool {
+        self.0.as_ptr() == rhs.0.as_ptr()
+    }
+}
+impl<T> Eq for SyncWeakHc<T> {}
+
+// each shard sweeps itself as it grows, like a Constable
+struct Shard<T, S> {
+    table: HashMap<T, SyncWeakHc<T>, S>,
+    threshold: usize,
+    // counted under the shard's lock, so threads interning into different shards never
+    // touch the same counter
+    hits: u64,
+    misses: u64,
+    collections: u64,
+}
+
+impl<T: Hash + Eq + Clone, S: BuildHasher> Shard<T, S> {
+    fn sweep(&mut self) {
+        self.table.retain(|_, value| value.0.strong_count() > 0);
+        self.threshold = next_threshold(self.table.len());
+        self.collections += 1;
+        stats::collected();
+    }
+
+    // `hash` is e's, so the table is probed once and e is never hashed again, unless
+    // the shard has to be swept first
+    fn intern(&mut self, hash: u64, e: T) -> (SyncHc<T>, bool) {
+        let full = self.table.len() >= self.threshold;
+        let hc = match self.table.raw_entry_mut().from_key_hashed_nocheck(hash, &e) {
+            RawEntryMut::Occupied(mut entry) => {
+                if let Some(hc) = entry.get().upgrade() {
+                    debug_assert!(*hc == e);
+                    self.hits += 1;
+                    stats::hit();
+                    return (hc, false);
+                }
+                // an equal term that has since died, whose slot we can take over
+                let hc = SyncHc(Arc::new(HcBox::new(hash, e)));
+                entry.insert(hc.downgrade());
+                hc
+            }
+            RawEntryMut::Vacant(_) if full => {
+                self.sweep();
+                return self.intern(hash, e);
+            }
+            RawEntryMut::Vacant(entry) => {
+                let hc = SyncHc(Arc::new(HcBox::new(hash, e.clone())));
+                entry.insert_hashed_nocheck(hash, e, hc.downgrade());
+                hc
+            }
+        };
+        self.misses += 1;
+        stats::miss();
+        (hc, true)
+    }
+}
+
+pub struct SyncConstable<T: Hash + Eq + Clone, S = RandomState> {
+    build_hasher: S,
+    shards: Box<[Mutex<Shard<T, S>>]>,
+}
+
+// a few shards per core keeps the odds of two threads wanting the same lock low
+fn default_shards() -> usize {
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    (4 * cores).next_power_of_two()
+}
+
+impl<T: Hash + Eq + Clone> SyncConstable<T, RandomState> {
+    #[inline]
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+    #[inline]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
+impl<T: Hash + Eq + Clone> Default for SyncConstable<T, RandomState> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Hash + Eq + Clone, S: BuildHasher + Clone> SyncConstable<T, S> {
+    #[inline]
+    pub fn with_hasher(build_hasher: S) -> Self {
+        Self::with_capacity_and_hasher(0, build_hasher)
+    }
+
+    #[inline]
+    pub fn with_capacity_and_hasher(capacity: usize, build_hasher: S) -> Self {
+        Self::with_shards(default_shards(), capacity, build_hasher)
+    }
+
+    /// `shards` is rounded up to a power of two.
+    pub fn with_shards(shards: usize, capacity: usize, build_hasher: S) -> Self {
+        let shards = shards.max(1).next_power_of_two();
+        let per_shard = capacity.div_ceil(shards);
+        SyncConstable {
+            shards: (0..shards)
+                .map(|_| {
+                    Mutex::new(Shard {
+                        table: HashMap::with_capacity_and_hasher(per_shard, build_hasher.clone()),
+                        threshold: next_threshold(per_shard),
+                        hits: 0,
+                        misses: 0,
+                        collections: 0,
+                    })
+                })
+                .collect(),
+            build_hasher,
+        }
+    }
+}
+
+impl<T: Hash + Eq + Clone, S: BuildHasher> SyncConstable<T, S> {
+    #[inline]
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    // the low bits of the hash pick the bucket within the shard's table, so use the high ones
+    fn shard(&self, hash: u64) -> MutexGuard<'_, Shard<T, S>> {
+        let bits = self.shards.len().trailing_zeros();
+        let i = if bits == 0 { 0 } else { (hash >> (64 - bits)) as usize };
+        lock(&self.shards[i])
+    }
+
+    fn each_shard<F: FnMut(&mut Shard<T, S>)>(&self, mut f: F) {
+        for shard in self.shards.iter() {
+            f(&mut lock(shard))
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| lock(shard).table.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| lock(shard).table.is_empty())
+    }
+
+    // locks each shard in turn, so under contention this is not an atomic snapshot
+    pub fn stats(&self) -> Stats {
+        let mut result = Stats::default();
+        self.each_shard(|shard| {
+            let live = shard.table.values().filter(|value| value.0.strong_count() > 0).count();
+            result.live += live;
+            result.dead += shard.table.len() - live;
+            result.hits += shard.hits;
+            result.misses += shard.misses;
+            result.collections += shard.collections;
+            result.bytes += stats::entry_bytes::<T, SyncWeakHc<T>>(shard.table.capacity()) + live * HcBox::<T>::FOOTPRINT;
+        });
+        result
+    }
+}
+
+// a panic while holding a shard can't leave the map half-updated, so poisoning is ignored
+fn lock<M>(m: &Mutex<M>) -> MutexGuard<'_, M> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl<T: Hash + Display + Eq + Clone, S: BuildHasher> Display for SyncConstable<T, S> {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "constable:")?;
+        for shard in self.shards.iter() {
+            for e in lock(shard).table.values() {
+                write!(fmt, "\n  | {}", e)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Hash + Debug + Eq + Clone, S: BuildHasher> Debug for SyncConstable<T, S> {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "constable:")?;
+        for shard in self.shards.iter() {
+            for e in lock(shard).table.values() {
+                write!(fmt, "\n  | {:?}", e)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// interning only needs a shared reference, so one table can be handed to many threads
+impl<T: Hash + Eq + Clone, S: BuildHasher> HashConstable<T> for &SyncConstable<T, S> {
+    type Handle = SyncHc<T>;
+
+    fn mk_is_new(self, e: T) -> (SyncHc<T>, bool) {
+        // children of e are hashed by their cached hashes, so this only walks the top node
+        let hash = self.build_hasher.hash_one(&e);
+        // the lookup and the insert happen under the same lock, so two threads interning
+        // equal terms agree on the result
+        self.shard(hash).intern(hash, e)
+    }
+
+    fn collect(self) {
+        self.each_shard(Shard::sweep)
+    }
+
+    fn shrink_to_fit(self) {
+        self.each_shard(|shard| shard.table.shrink_to_fit())
+    }
+
+    fn collect_to_fit(self) {
+        self.each_shard(|shard| {
+            shard.sweep();
+            shard.table.shrink_to_fit()
+        })
+    }
+
+    fn reserve(self, additional: usize) {
+        let per_shard = additional.div_ceil(self.shards.len());
+        self.each_shard(|shard| shard.table.reserve(per_shard))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+    use std::sync::Barrier;
+
+    #[derive(Clone, PartialEq, Eq, Hash, Debug)]
+    enum Term {
+        Var(u32),
+        App(SyncHc<Term>, SyncHc<Term>),
+    }
+
+    // builds the same spine of applications from every thread
+    fn spine(table: &SyncConstable<Term>, n: u32) -> SyncHc<Term> {
+        let mut t = table.mk(Term::Var(0));
+        for i in 1..n {
+            let v = table.mk(Term::Var(i % 7));
+            t = table.mk(Term::App(t, v));
+        }
+        t
+    }
+
+    #[test]
+    fn shared_across_threads() {
+        const THREADS: usize = 16;
+        let table = SyncConstable::with_shards(4, 0, RandomState::new());
+        let barrier = Barrier::new(THREADS);
+        let results: Vec<(SyncHc<Term>, usize)> = thread::scope(|s| {
+            let handles: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    s.spawn(|| {
+                        barrier.wait();
+                        let t = spine(&table, 500);
+                        let news = (0..100).filter(|i| table.mk_is_new(Term::Var(1000 + i)).1).count();
+                        (t, news)
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        // everyone built the same term
+        assert!(results.iter().all(|r| r.0 == results[0].0));
+        // the fresh variables were dropped straight away, so each thread may have
+        // rebuilt some, but never fewer than one thread's worth in total
+        assert!(results.iter().map(|r| r.1).sum::<usize>() >= 100);
+        let t = spine(&table, 500);
+        assert_eq!(t, spine(&table, 500));
+        table.collect();
+        assert_eq!(table.len(), 7 + 499);
+        let stats = table.stats();
+        assert_eq!((stats.live, stats.dead), (7 + 499, 0));
+        assert_eq!(stats.hits + stats.misses, THREADS as u64 * (2 * 499 + 1 + 100) + 2 * (2 * 499 + 1));
+        // the threads have exited, but what they counted is still in the totals
+        let totals = ::totals();
+        assert!(totals.hits + totals.misses >= stats.hits + stats.misses);
+    }
+
+    #[test]
+    fn collects() {
+        let table = SyncConstable::new();
+        let (x, new) = table.mk_is_new(Term::Var(1));
+        assert!(new);
+        assert!(!table.mk_is_new(Term::Var(1)).1);
+        assert_eq!(table.len(), 1);
+        drop(x);
+        table.collect_to_fit();
+        assert!(table.is_empty());
+        assert!(table.mk_is_new(Term::Var(1)).1);
+    }
+
+    // counts the hashes finished by every hasher it builds
+    #[derive(Default, Clone)]
+    struct Counting(Arc<AtomicUsize>);
+    struct CountingHasher(DefaultHasher, Arc<AtomicUsize>);
+
+    impl BuildHasher for Counting {
+        type Hasher = CountingHasher;
+        fn build_hasher(&self) -> CountingHasher {
+            CountingHasher(DefaultHasher::new(), self.0.clone())
+        }
+    }
+
+    impl Hasher for CountingHasher {
+        fn finish(&self) -> u64 {
+            self.1.fetch_add(1, AtomicOrdering::Relaxed);
+            self.0.finish()
+        }
+        fn write(&mut self, bytes: &[u8]) {
+            self.0.write(bytes)
+        }
+    }
+
+    #[test]
+    fn hashes_once() {
+        let counting = Counting::default();
+        // big enough that no shard grows or sweeps, as either would rehash
+        let table = SyncConstable::with_shards(4, 1000, counting.clone());
+        let xs: Vec<_> = (0..100).map(|i| table.mk(Term::Var(i))).collect();
+        assert_eq!(counting.0.load(AtomicOrdering::Relaxed), 100);
+        for i in 0..100 {
+            assert_eq!(table.mk(Term::Var(i)), xs[i as usize]);
+        }
+        assert_eq!(counting.0.load(AtomicOrdering::Relaxed), 200);
+        // a dead entry is taken over in place
+        drop(xs);
+        assert!(table.mk_is_new(Term::Var(3)).1);
+        assert_eq!(counting.0.load(AtomicOrdering::Relaxed), 201);
+        assert_eq!(table.len(), 100);
+    }
+}
@@ -0,0 +1,421 @@
+// thunks whose initializer is a future
+//
+// the initializer is polled with a waker of our own, which wakes everyone awaiting the
+// value. the first of them to run afterwards polls the initializer again and the rest just
+// go back to waiting, so it is polled at most once per wakeup no matter how many are
+// waiting, and it doesn't matter which of them polled it last or whether that one has since
+// been dropped.
+//
+// a Lazy can't be awaited, as the only way to get at its value is to run its thunk on the
+// executor's thread. wrap the work in an AsyncLazy instead, with an async initializer.
+
+use std::cell::UnsafeCell;
+use std::fmt::{self, Debug, Formatter};
+use std::future::{self, Future};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Wake, Waker};
+
+use super::{Lazy, LazyError};
+
+enum State<'f, T> {
+  Pending(Pin<Box<dyn Future<Output = T> + 'f>>),
+  // the initializer has been taken out and is being polled
+  Polling,
+  Ready(T),
+  Poisoned,
+}
+
+struct Waiters {
+  wakers: Mutex<Vec<Waker>>,
+  // has the initializer been woken since it was last polled?
+  woken: AtomicBool,
+}
+
+impl Default for Waiters {
+  fn default() -> Self {
+    Waiters { wakers: Mutex::new(Vec::new()), woken: AtomicBool::new(true) }
+  }
+}
+
+impl Waiters {
+  fn register(&self, waker: &Waker) {
+    let mut ws = self.wakers.lock().unwrap_or_else(PoisonError::into_inner);
+    if !ws.iter().any(|w| w.will_wake(waker)) {
+      ws.push(waker.clone())
+    }
+  }
+  fn wake_all(&self) {
+    let ws = mem::take(&mut *self.wakers.lock().unwrap_or_else(PoisonError::into_inner));
+    for w in ws {
+      w.wake()
+    }
+  }
+}
+
+impl Wake for Waiters {
+  fn wake(self: Arc<Self>) {
+    self.wake_by_ref()
+  }
+  fn wake_by_ref(self: &Arc<Self>) {
+    self.woken.store(true, Ordering::Release);
+    self.wake_all()
+  }
+}
+
+// like LazyVal, we only borrow the state mutably while it holds no value
+pub struct AsyncLazyVal<'f, T> {
+  state: UnsafeCell<State<'f, T>>,
+  waiters: Arc<Waiters>,
+}
+
+impl<'f, T: 'f> AsyncLazyVal<'f, T> {
+  pub fn new<F: Future<Output = T> + 'f>(f: F) -> Self {
+    AsyncLazyVal {
+      state: UnsafeCell::new(State::Pending(Box::pin(f))),
+      waiters: Arc::default(),
+    }
+  }
+
+  #[inline]
+  fn state(&self) -> &State<'f, T> {
+    unsafe { &*self.state.get() }
+  }
+
+  pub fn ready(&self) -> bool {
+    matches!(self.state(), State::Ready(_))
+  }
+
+  pub fn is_poisoned(&self) -> bool {
+    matches!(self.state(), State::Poisoned)
+  }
+
+  pub fn try_get(&self) -> Option<&T> {
+    match self.state() {
+      State::Ready(t) => Some(t),
+      _ => None,
+    }
+  }
+
+  pub fn poll_get(&self, cx: &mut Context<'_>) -> Poll<Result<&T, LazyError>> {
+    match self.state() {
+      State::Ready(t) => return Poll::Ready(Ok(t)),
+      State::Polling => return Poll::Ready(Err(LazyError::Cycle)),
+      State::Poisoned => return Poll::Ready(Err(LazyError::Poisoned)),
+      State::Pending(_) => {}
+    }
+    self.waiters.register(cx.waker());
+    if !self.waiters.woken.swap(false, Ordering::AcqRel) {
+      return Poll::Pending;
+    }
+    let mut f = match mem::replace(unsafe { &mut *self.state.get() }, State::Polling) {
+      State::Pending(f) => f,
+      _ => unreachable!(),
+    };
+    let waker = Waker::from(self.waiters.clone());
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f.as_mut().poll(&mut Context::from_waker(&waker))));
+    let state = unsafe { &mut *self.state.get() };
+    match result {
+      Ok(Poll::Pending) => {
+        *state = State::Pending(f);
+        Poll::Pending
+      }
+      Ok(Poll::Ready(t)) => {
+        *state = State::Ready(t);
+        self.waiters.wake_all();
+        Poll::Ready(Ok(self.try_get().unwrap()))
+      }
+      Err(e) => {
+        *state = State::Poisoned;
+        self.waiters.wake_all();
+        panic::resume_unwind(e)
+      }
+    }
+  }
+
+  pub fn get_checked(&self) -> GetChecked<'_, 'f, T> {
+    GetChecked(self)
+  }
+
+  pub fn get(&self) -> Get<'_, 'f, T> {
+    Get(self)
+  }
+}
+
+// the future returned by AsyncLazyVal::get_checked
+#[derive(Debug)]
+pub struct GetChecked<'a, 'f: 'a, T: 'f>(&'a AsyncLazyVal<'f, T>);
+
+impl<'a, 'f, T: 'f> Future for GetChecked<'a, 'f, T> {
+  type Output = Result<&'a T, LazyError>;
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    self.0.poll_get(cx)
+  }
+}
+
+// the future returned by AsyncLazyVal::get
+#[derive(Debug)]
+pub struct Get<'a, 'f: 'a, T: 'f>(&'a AsyncLazyVal<'f, T>);
+
+impl<'a, 'f, T: 'f> Future for Get<'a, 'f, T> {
+  type Output = &'a T;
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<&'a T> {
+    self.0.poll_get(cx).map(|r| r.unwrap_or_else(|e| panic!("{}", e)))
+  }
+}
+
+impl<'f, T: 'f> From<T> for AsyncLazyVal<'f, T> {
+  fn from(that: T) -> Self {
+    AsyncLazyVal {
+      state: UnsafeCell::new(State::Ready(that)),
+      waiters: Arc::default(),
+    }
+  }
+}
+
+impl<'f, T: Debug + 'f> Debug for AsyncLazyVal<'f, T> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self.state() {
+      State::Pending(_) => f.write_str("<future>"),
+      State::Polling => f.write_str("<polling>"),
+      State::Ready(t) => Debug::fmt(t, f),
+      State::Poisoned => f.write_str("<poisoned>"),
+    }
+  }
+}
+
+// a shared, single threaded, lazily awaited value
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct AsyncLazy<'f, T: 'f>(pub Rc<AsyncLazyVal<'f, T>>);
+
+impl<'f, T: 'f> Clone for AsyncLazy<'f, T> {
+  fn clone(&self) -> Self {
+    AsyncLazy(self.0.clone())
+  }
+
+  fn clone_from(&mut self, source: &Self) {
+    self.0.clone_from(&source.0)
+  }
+}
+
+impl<'f, T: 'f> AsyncLazy<'f, T> {
+  pub fn new<F: Future<Output = T> + 'f>(f: F) -> Self {
+    AsyncLazy(Rc::new(AsyncLazyVal::new(f)))
+  }
+  pub fn new_strict(value: T) -> Self {
+    AsyncLazy(Rc::new(AsyncLazyVal::from(value)))
+  }
+  pub fn ready(&self) -> bool {
+    self.0.ready()
+  }
+  pub fn is_poisoned(&self) -> bool {
+    self.0.is_poisoned()
+  }
+  pub fn try_get(&self) -> Option<&T> {
+    self.0.try_get()
+  }
+  pub fn poll_get(&self, cx: &mut Context<'_>) -> Poll<Result<&T, LazyError>> {
+    self.0.poll_get(cx)
+  }
+  pub fn get_checked(&self) -> GetChecked<'_, 'f, T> {
+    self.0.get_checked()
+  }
+  pub fn get(&self) -> Get<'_, 'f, T> {
+    self.0.get()
+  }
+  pub fn map<'g, U, F: (FnOnce(&T) -> U) + 'g>(&self, f: F) -> AsyncLazy<'g, U> where
+    'f: 'g, T: 'g, U: 'g, {
+    let me = self.clone();
+    let mut f = Some(f);
+    AsyncLazy::new(future::poll_fn(move |cx| {
+      let t = match me.poll_get(cx) {
+        Poll::Pending => return Poll::Pending,
+        Poll::Ready(r) => r.unwrap_or_else(|e| panic!("{}", e)),
+      };
+      Poll::Ready(f.take().unwrap()(t))
+    }))
+  }
+  pub fn map2<'g, 'h, U, V, F: (FnOnce(&T, &U) -> V) + 'h>(
+    this: &AsyncLazy<'f, T>,
+    that: &AsyncLazy<'g, U>,
+    f: F,
+  ) -> AsyncLazy<'h, V> where
+    'f: 'h, 'g: 'h, T: 'h, U: 'h, {
+    let a = this.clone();
+    let b = that.clone();
+    let mut f = Some(f);
+    AsyncLazy::new(future::poll_fn(move |cx| {
+      // poll both, so they make progress together
+      let (x, y) = match (a.poll_get(cx), b.poll_get(cx)) {
+        (Poll::Ready(x), Poll::Ready(y)) => (x, y),
+        _ => return Poll::Pending,
+      };
+      let x = x.unwrap_or_else(|e| panic!("{}", e));
+      let y = y.unwrap_or_else(|e| panic!("{}", e));
+      Poll::Ready(f.take().unwrap()(x, y))
+    }))
+  }
+  // combine with a synchronous thunk, which is forced once this is ready
+  pub fn map_lazy<'g, 'h, U, V, F: (FnOnce(&T, &U) -> V) + 'h>(
+    &self,
+    that: &Lazy<'g, U>,
+    f: F,
+  ) -> AsyncLazy<'h, V> where
+    'f: 'h, 'g: 'h, T: 'h, U: 'h, {
+    let that = that.clone();
+    self.map(move |t| f(t, that.get()))
+  }
+}
+
+impl<'f, T: 'f> From<T> for AsyncLazy<'f, T> {
+  #[inline]
+  fn from(that: T) -> Self {
+    AsyncLazy::new_strict(that)
+  }
+}
+
+// awaiting an AsyncLazy, like consuming a Lazy, clones the result
+impl<'f, T: Clone + 'f> Future for AsyncLazy<'f, T> {
+  type Output = T;
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+    self.0.poll_get(cx).map(|r| r.unwrap_or_else(|e| panic!("{}", e)).clone())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::cell::{Cell, RefCell};
+
+  // a tiny single threaded executor: round robin over whichever tasks have been woken
+  struct Flag(AtomicBool);
+
+  impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+      self.0.store(true, Ordering::SeqCst)
+    }
+  }
+
+  type Task<'a> = Pin<Box<dyn Future<Output = ()> + 'a>>;
+
+  fn run(tasks: Vec<Task<'_>>) {
+    let mut tasks: Vec<_> = tasks.into_iter().map(|t| (Some(t), Arc::new(Flag(AtomicBool::new(true))))).collect();
+    while tasks.iter().any(|(t, _)| t.is_some()) {
+      let mut progress = false;
+      for (task, flag) in tasks.iter_mut() {
+        if task.is_some() && flag.0.swap(false, Ordering::SeqCst) {
+          progress = true;
+          let waker = Waker::from(flag.clone());
+          if task.as_mut().unwrap().as_mut().poll(&mut Context::from_waker(&waker)).is_ready() {
+            *task = None
+          }
+        }
+      }
+      assert!(progress, "every task is waiting and none have been woken");
+    }
+  }
+
+  // await f, then hand its result to k
+  fn then<'a, F: Future + 'a, U, K: (FnOnce(F::Output) -> U) + 'a>(f: F, k: K) -> impl Future<Output = U> + 'a {
+    let mut f = Box::pin(f);
+    let mut k = Some(k);
+    future::poll_fn(move |cx| f.as_mut().poll(cx).map(|x| k.take().unwrap()(x)))
+  }
+
+  fn block_on<'a, F: Future + 'a>(f: F) -> F::Output {
+    let out = RefCell::new(None);
+    run(vec![Box::pin(then(f, |x| *out.borrow_mut() = Some(x)))]);
+    out.into_inner().unwrap()
+  }
+
+  // pending until fired
+  #[derive(Clone, Default)]
+  struct Signal(Rc<RefCell<(bool, Option<Waker>)>>);
+
+  impl Signal {
+    fn fire(&self) -> impl Future<Output = ()> {
+      let s = self.clone();
+      future::poll_fn(move |_| {
+        let mut s = s.0.borrow_mut();
+        s.0 = true;
+        if let Some(w) = s.1.take() {
+          w.wake()
+        }
+        Poll::Ready(())
+      })
+    }
+    fn wait(&self) -> impl Future<Output = ()> {
+      let s = self.clone();
+      future::poll_fn(move |cx| {
+        let mut s = s.0.borrow_mut();
+        if s.0 {
+          Poll::Ready(())
+        } else {
+          s.1 = Some(cx.waker().clone());
+          Poll::Pending
+        }
+      })
+    }
+  }
+
+  #[test]
+  fn polled_once() {
+    let signal = Signal::default();
+    let polls = Rc::new(Cell::new(0));
+    let p = polls.clone();
+    let mut wait = Box::pin(signal.wait());
+    let x: AsyncLazy<u32> = AsyncLazy::new(future::poll_fn(move |cx| {
+      p.set(p.get() + 1);
+      wait.as_mut().poll(cx).map(|()| 42)
+    }));
+    let seen = Cell::new(0);
+    let waiter = || then(x.clone(), |n| seen.set(seen.get() + n));
+    run(vec![Box::pin(waiter()), Box::pin(waiter()), Box::pin(waiter()), Box::pin(signal.fire())]);
+    assert_eq!(seen.get(), 126);
+    // once to start it, once when the signal woke it
+    assert_eq!(polls.get(), 2);
+    assert_eq!(x.try_get(), Some(&42));
+  }
+
+  #[test]
+  fn maps() {
+    let signal = Signal::default();
+    let x: AsyncLazy<u32> = AsyncLazy::new(then(signal.wait(), |()| 2));
+    let y = AsyncLazy::from(3);
+    let z = x.map(|n| n * 10);
+    let w = AsyncLazy::map2(&z, &y, |a, b| a + b);
+    let v = w.map_lazy(&Lazy::new(|| 100), |a, b| a + b);
+    let out = Cell::new(0);
+    run(vec![Box::pin(then(v.clone(), |n| out.set(n))), Box::pin(signal.fire())]);
+    assert_eq!(out.get(), 123);
+    assert!(x.ready() && y.ready());
+    assert_eq!(block_on(v.get()), &123);
+  }
+
+  #[test]
+  fn cycle() {
+    let me: Rc<RefCell<Option<AsyncLazy<u32>>>> = Rc::new(RefCell::new(None));
+    let m = me.clone();
+    let x = AsyncLazy::new(future::poll_fn(move |cx| {
+      let this = RefCell::borrow(&m).clone().unwrap();
+      let r = this.poll_get(cx).map(|r| if r == Err(LazyError::Cycle) { 0 } else { 1 });
+      r
+    }));
+    *me.borrow_mut() = Some(x.clone());
+    assert_eq!(block_on(x.get_checked()), Ok(&0));
+    *me.borrow_mut() = None;
+  }
+
+  #[test]
+  fn poisoned() {
+    let x: AsyncLazy<u32> = AsyncLazy::new(future::poll_fn(|_| panic!("boom")));
+    assert!(panic::catch_unwind(AssertUnwindSafe(|| block_on(x.get()))).is_err());
+    assert!(x.is_poisoned());
+    assert_eq!(block_on(x.get_checked()), Err(LazyError::Poisoned));
+  }
+}
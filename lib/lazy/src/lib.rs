@@ -1,25 +1,65 @@
-
+#![feature(exact_size_is_empty)]
+#![feature(trusted_len)]
 
 use std::borrow::Borrow;
 use std::boxed::Box;
 use std::cell::UnsafeCell;
-use std::fmt::{self, Debug, Formatter};
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
 use std::mem;
-use std::ops::{Deref, Fn, FnMut, FnOnce};
+use std::ops::{Deref, FnOnce};
+use std::panic::{self, AssertUnwindSafe};
 use std::rc::Rc;
+use std::thread;
+
+extern crate kanso_fun;
+
+pub mod sync;
+pub mod future;
+pub mod stream;
+
+pub use sync::{SyncLazy, SyncLazyVal};
+pub use future::{AsyncLazy, AsyncLazyVal};
+pub use stream::Stream;
+
+// why a thunk could not produce its value
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LazyError {
+  // the thunk demanded its own value while it was being forced
+  Cycle,
+  // an earlier attempt to force the thunk panicked
+  Poisoned,
+}
+
+impl Display for LazyError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      LazyError::Cycle => f.write_str("<infinite loop>"),
+      LazyError::Poisoned => f.write_str("<poisoned>"),
+    }
+  }
+}
+
+impl Error for LazyError {}
 
 // almost has the semantics of a scala lazy val
 // but requires mutation
 pub enum Closure<'f, T> {
   Delayed(Box<dyn (FnOnce() -> T) + 'f>),
+  // the closure has been taken out and is running
+  Forcing,
   Forced(T),
+  // the closure panicked
+  Poisoned,
 }
 
 impl<'f, T: Debug> Debug for Closure<'f, T> {
   fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
     match self {
       Closure::Delayed(_) => f.write_str("<closure>"),
+      Closure::Forcing => f.write_str("<forcing>"),
       Closure::Forced(t) => Debug::fmt(&t, f),
+      Closure::Poisoned => f.write_str("<poisoned>"),
     }
   }
 }
@@ -38,16 +78,50 @@ impl<'f, T: 'f> Closure<'f, T> {
     }
   }
 
+  // takes the closure out if it still needs to be run, leaving us Forcing
+  fn start(&mut self) -> Result<Option<Box<dyn (FnOnce() -> T) + 'f>>, LazyError> {
+    match self {
+      Closure::Forced(_) => Ok(None),
+      Closure::Forcing => Err(LazyError::Cycle),
+      Closure::Poisoned => Err(LazyError::Poisoned),
+      Closure::Delayed(_) => match mem::replace(self, Closure::Forcing) {
+        Closure::Delayed(f) => Ok(Some(f)),
+        _ => unreachable!(),
+      },
+    }
+  }
+
+  // a panic in the closure leaves us Poisoned and carries on unwinding
+  fn finish(&mut self, result: thread::Result<T>) {
+    match result {
+      Ok(t) => *self = Closure::Forced(t),
+      Err(e) => {
+        *self = Closure::Poisoned;
+        panic::resume_unwind(e)
+      }
+    }
+  }
+
+  pub fn try_force(&mut self) -> Result<(), LazyError> {
+    if let Some(f) = self.start()? {
+      let result = panic::catch_unwind(AssertUnwindSafe(f));
+      self.finish(result)
+    }
+    Ok(())
+  }
+
   #[inline]
   pub fn seq(&mut self) {
-    let mut mf = None;
-    if let Closure::Delayed(ref mut fr) = self {
-      mf = Some(mem::replace(fr, detail::blackhole()))
-    }
-    if let Some(f) = mf {
-      *self = Closure::Forced(f())
+    if let Err(e) = self.try_force() {
+      panic!("{}", e)
     }
   }
+
+  pub fn get_checked(&mut self) -> Result<&T, LazyError> {
+    self.try_force()?;
+    Ok(self.try_get().unwrap())
+  }
+
   #[inline]
   pub fn get(&mut self) -> &T {
     self.seq();
@@ -70,6 +144,8 @@ impl<'f, T: 'f> Closure<'f, T> {
     match self {
       Closure::Delayed(f) => f(),
       Closure::Forced(t) => t,
+      Closure::Forcing => panic!("{}", LazyError::Cycle),
+      Closure::Poisoned => panic!("{}", LazyError::Poisoned),
     }
   }
 
@@ -95,8 +171,7 @@ impl<'f, T: 'f> Closure<'f, T> {
     if let Closure::Forced(value) = self {
       Lazy::from(value.clone())
     } else {
-      let placeholder = Closure::new(|| unreachable!());
-      let old_guts = mem::replace(self, placeholder);
+      let old_guts = mem::replace(self, Closure::Forcing);
       let result = Lazy(Rc::new(LazyVal(UnsafeCell::new(old_guts))));
       let clone = result.clone();
       let new_guts = Closure::new(move || clone.get().clone());
@@ -129,6 +204,10 @@ impl<'f, T: 'f> IntoIterator for Closure<'f, T> {
 
 // this is a scala-style 'lazy val'. with all the upsides
 // and downsides that would entail
+//
+// references handed out by get are tied to the LazyVal, and we only ever borrow the cell
+// mutably while it holds no value, i.e. to take the closure out or to put the result in,
+// so they stay valid for as long as the borrow they came from.
 #[derive(Debug)]
 pub struct LazyVal<'f, T>(UnsafeCell<Closure<'f, T>>);
 
@@ -136,17 +215,45 @@ impl<'f, T: 'f> LazyVal<'f, T> {
   pub fn new<F: (FnOnce() -> T) + 'f>(f: F) -> Self {
     LazyVal(UnsafeCell::new(Closure::new(f)))
   }
+  #[inline]
+  fn closure(&self) -> &Closure<'f, T> {
+    unsafe { &*self.0.get() }
+  }
+  // the closure runs without a borrow of the cell outstanding, so it may force us again
+  pub fn try_force(&self) -> Result<(), LazyError> {
+    match self.closure() {
+      Closure::Forced(_) => return Ok(()),
+      Closure::Forcing => return Err(LazyError::Cycle),
+      Closure::Poisoned => return Err(LazyError::Poisoned),
+      Closure::Delayed(_) => {}
+    }
+    if let Some(f) = unsafe { &mut *self.0.get() }.start()? {
+      let result = panic::catch_unwind(AssertUnwindSafe(f));
+      unsafe { &mut *self.0.get() }.finish(result)
+    }
+    Ok(())
+  }
+  pub fn get_checked(&self) -> Result<&T, LazyError> {
+    self.try_force()?;
+    Ok(self.closure().try_get().unwrap())
+  }
+  pub fn is_poisoned(&self) -> bool {
+    matches!(self.closure(), Closure::Poisoned)
+  }
   pub fn seq(&self) {
-    unsafe { &mut *self.0.get() }.seq()
+    if let Err(e) = self.try_force() {
+      panic!("{}", e)
+    }
   }
   pub fn ready(&self) -> bool {
-    unsafe { &*self.0.get() }.ready()
+    self.closure().ready()
   }
-  pub fn get(&self) -> &'f T {
-    unsafe { &mut *self.0.get() }.get()
+  pub fn get(&self) -> &T {
+    self.seq();
+    self.closure().try_get().unwrap()
   }
-  pub fn try_get(&self) -> Option<&'f T> {
-    unsafe { &*self.0.get() }.try_get()
+  pub fn try_get(&self) -> Option<&T> {
+    self.closure().try_get()
   }
   pub fn consume(self) -> T {
     self.0.into_inner().consume()
@@ -155,12 +262,18 @@ impl<'f, T: 'f> LazyVal<'f, T> {
     self.0.into_inner().try_consume()
   }
   pub fn map_consume<'g, U, F>(self, f: F) -> LazyVal<'g, U> where
-    'f: 'g, U: 'g, T: 'g, F: (FnOnce(&'f T) -> U) + 'g, {
-      LazyVal::new(move || f(self.get()))
+    'f: 'g, U: 'g, T: 'g, F: (FnOnce(&T) -> U) + 'g, {
+      LazyVal::new(move || f(&self.consume()))
   }
+  // promoting a thunk from inside its own closure would swap the closure out from under
+  // the forcing, so we treat it as the cycle it is
   pub fn promote(&self) -> Lazy<'f, T> where
     T: 'f + Clone, {
-      unsafe { &mut *self.0.get() }.promote()
+      match self.closure() {
+        Closure::Forced(value) => Lazy::from(value.clone()),
+        Closure::Forcing => panic!("{}", LazyError::Cycle),
+        _ => unsafe { &mut *self.0.get() }.promote(),
+      }
   }
 }
 
@@ -240,13 +353,22 @@ impl<'f, T: 'f> Lazy<'f, T> {
   pub fn seq(&self) {
     self.0.as_ref().seq()
   }
+  pub fn try_force(&self) -> Result<(), LazyError> {
+    self.0.as_ref().try_force()
+  }
+  pub fn get_checked(&self) -> Result<&T, LazyError> {
+    self.0.as_ref().get_checked()
+  }
+  pub fn is_poisoned(&self) -> bool {
+    self.0.as_ref().is_poisoned()
+  }
   pub fn ready(&self) -> bool {
     self.0.as_ref().ready()
   }
-  pub fn get(&self) -> &'f T {
+  pub fn get(&self) -> &T {
     self.0.as_ref().get()
   }
-  pub fn try_get(&self) -> Option<&'f T> {
+  pub fn try_get(&self) -> Option<&T> {
     self.0.as_ref().try_get()
   }
   pub fn map<'g, U, F: (FnOnce(&T) -> U) + 'g>(&self, f: F) -> Lazy<'g, U> where
@@ -265,6 +387,22 @@ impl<'f, T: 'f> Lazy<'f, T> {
     Lazy::new(move || f(a.get(), b.get()))
   }
 
+  // the thunk returned by f is forced along with the result
+  pub fn and_then<'g, U: Clone + 'g, F: (FnOnce(&T) -> Lazy<'g, U>) + 'g>(&self, f: F) -> Lazy<'g, U> where
+    'f: 'g, T: 'g, {
+    let me = self.clone();
+    Lazy::new(move || f(me.get()).consume())
+  }
+  pub fn zip<'g, 'h, U: Clone + 'h>(&self, that: &Lazy<'g, U>) -> Lazy<'h, (T, U)> where
+    'f: 'h, 'g: 'h, T: Clone + 'h, {
+    Lazy::map2(self, that, |a, b| (a.clone(), b.clone()))
+  }
+  // forcing the result forces every thunk in order
+  pub fn sequence<I: IntoIterator<Item = Lazy<'f, T>>>(iter: I) -> Lazy<'f, Vec<T>> where T: Clone, {
+    let xs: Vec<Lazy<'f, T>> = iter.into_iter().collect();
+    Lazy::new(move || xs.into_iter().map(Lazy::consume).collect())
+  }
+
   // consumes this lazy value in an effort to try to avoid cloning the contents
   pub fn consume(self) -> T where T: Clone, {
     match Rc::try_unwrap(self.0) {
@@ -280,6 +418,12 @@ impl<'f, T: 'f> Lazy<'f, T> {
   }
 }
 
+impl<'f, T: 'f + Clone> Lazy<'f, Lazy<'f, T>> {
+  pub fn flatten(&self) -> Lazy<'f, T> {
+    self.and_then(Lazy::clone)
+  }
+}
+
 impl<'f, T: 'f + Default> Default for Lazy<'f, T> {
   fn default() -> Self {
     Lazy::new(|| T::default())
@@ -293,25 +437,6 @@ impl<'f, T: 'f> From<T> for Lazy<'f, T> {
   }
 }
 
-impl<'f, T: 'f> FnOnce<()> for Lazy<'f, T> {
-  type Output = &'f T;
-  extern "rust-call" fn call_once(self, _args: ()) -> &'f T {
-    self.0.as_ref().get()
-  }
-}
-
-impl<'f, T: 'f> FnMut<()> for Lazy<'f, T> {
-  extern "rust-call" fn call_mut(&mut self, _args: ()) -> &'f T {
-    self.0.as_ref().get()
-  }
-}
-
-impl<'f, T: 'f> Fn<()> for Lazy<'f, T> {
-  extern "rust-call" fn call(&self, _args: ()) -> &'f T {
-    self.0.as_ref().get()
-  }
-}
-
 impl<'f, T: 'f> Borrow<T> for Lazy<'f, T> {
   fn borrow(&self) -> &T {
     self.get()
@@ -339,29 +464,10 @@ impl<'f, T: 'f + Clone> IntoIterator for Lazy<'f, T> {
   }
 }
 
-/*
-impl <'f, T : Clone> Future for Lazy<'f,T> {
-  type Output = T;
-  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-    let this = self.into_inner()
-    if Some(t) = self.try_get()
-    ...
-  }
-}
-*/
-
 pub mod detail {
   use std::iter::{ExactSizeIterator,TrustedLen};
   use super::*;
 
-  pub fn blackhole<'f, T: 'f>() -> Box<dyn (FnOnce() -> T) + 'f> {
-    Box::new(|| panic!("<infinite loop>"))
-  }
-
-  pub fn promoting<'f, T: 'f>() -> Box<dyn (FnOnce() -> T) + 'f> {
-    Box::new(|| unreachable!())
-  }
-
   #[derive(Debug)]
   #[repr(transparent)]
   pub struct ClosureIterator<'f, T: 'f>(pub Option<Closure<'f, T>>);
@@ -458,10 +564,167 @@ pub fn main() {
     y * 10
   });
   let w = x.map(|r| r + 1);
-  println!("{}", w());
+  println!("{}", w.get());
   println!("{}", w.get());
   println!("{}", *w); // deref makes for nice syntax
   for z in w {
     println!("{}", z);
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::cell::RefCell;
+
+  #[test]
+  fn combinators() {
+    let forced = Rc::new(RefCell::new(Vec::new()));
+    let thunk = |n: u32| {
+      let forced = forced.clone();
+      Lazy::new(move || {
+        forced.borrow_mut().push(n);
+        n
+      })
+    };
+    let xs = Lazy::sequence(vec![thunk(1), thunk(2), thunk(3)]);
+    let pair = thunk(4).zip(&thunk(5));
+    let bound = thunk(6).and_then(|n| thunk(n + 1));
+    let nested = Lazy::new_strict(thunk(8)).flatten();
+    assert!(RefCell::borrow(&forced).is_empty());
+    assert_eq!(*xs.get(), vec![1, 2, 3]);
+    assert_eq!(*pair.get(), (4, 5));
+    assert_eq!(*bound.get(), 7);
+    assert_eq!(*nested.get(), 8);
+    assert_eq!(*RefCell::borrow(&forced), vec![1, 2, 3, 4, 5, 6, 7, 8]);
+  }
+
+  #[test]
+  fn cycle() {
+    let me: Rc<RefCell<Option<Lazy<u32>>>> = Rc::new(RefCell::new(None));
+    let m = me.clone();
+    let x = Lazy::new(move || {
+      let this = RefCell::borrow(&m).clone().unwrap();
+      match this.get_checked() {
+        Err(LazyError::Cycle) => 0,
+        _ => 1,
+      }
+    });
+    *me.borrow_mut() = Some(x.clone());
+    assert_eq!(x.get_checked(), Ok(&0));
+    assert_eq!(*x, 0);
+    *me.borrow_mut() = None;
+  }
+
+  #[test]
+  fn poisoned() {
+    let x: Lazy<u32> = Lazy::new(|| panic!("boom"));
+    let y = x.map(|n: &u32| n + 1);
+    assert!(panic::catch_unwind(AssertUnwindSafe(|| y.seq())).is_err());
+    assert!(x.is_poisoned());
+    assert!(y.is_poisoned());
+    assert_eq!(x.get_checked(), Err(LazyError::Poisoned));
+    assert_eq!(y.try_force(), Err(LazyError::Poisoned));
+    let mut c: Closure<u32> = Closure::new(|| 3);
+    assert_eq!(c.get_checked(), Ok(&3));
+  }
+
+  // the rest of these are mostly here to be run under `make miri`
+
+  #[test]
+  fn forcing() {
+    let count = Rc::new(std::cell::Cell::new(0));
+    let c = count.clone();
+    let x = Lazy::new(move || {
+      c.set(c.get() + 1);
+      vec![1, 2, 3]
+    });
+    assert!(!x.ready());
+    assert_eq!(x.try_get(), None);
+    let r = x.get();
+    // forcing again, or asking after the state, must not invalidate r
+    x.seq();
+    assert!(x.ready());
+    assert_eq!(x.try_force(), Ok(()));
+    assert_eq!(x.get_checked(), Ok(r));
+    assert_eq!(*r, vec![1, 2, 3]);
+    assert_eq!(x.len(), 3);
+    assert_eq!(count.get(), 1);
+  }
+
+  #[test]
+  fn promotion() {
+    let mut c = Closure::new(|| String::from("promoted"));
+    let l = c.promote();
+    assert!(!l.ready());
+    assert_eq!(c.get(), "promoted");
+    assert!(l.ready());
+    let v = LazyVal::new(|| 5);
+    let p = v.promote();
+    assert_eq!(*v, 5);
+    assert_eq!(*p, 5);
+    let q = v.promote();
+    assert!(q.ready());
+    assert_eq!(q.consume(), 5);
+  }
+
+  #[test]
+  fn promote_while_forcing() {
+    let me: Rc<RefCell<Option<Lazy<u32>>>> = Rc::new(RefCell::new(None));
+    let m = me.clone();
+    let x = Lazy::new(move || {
+      let this = RefCell::borrow(&m).clone().unwrap();
+      panic::catch_unwind(AssertUnwindSafe(|| this.0.promote())).map_or(0, |_| 1)
+    });
+    *me.borrow_mut() = Some(x.clone());
+    assert_eq!(*x, 0);
+    *me.borrow_mut() = None;
+  }
+
+  #[test]
+  fn maps() {
+    let x = Lazy::new(|| 2);
+    let y = Lazy::new(|| String::from("ab"));
+    let z = x.map(|n| n * 10);
+    let w = Lazy::map2(&z, &y, |n, s| format!("{}{}", s, n));
+    assert!(!x.ready());
+    assert_eq!(w.get(), "ab20");
+    assert!(x.ready() && y.ready() && z.ready());
+    let c = Closure::new(|| 4).map_consume(|n| n + 1);
+    assert_eq!(c.consume(), 5);
+    let v = LazyVal::new(|| 4).map_consume(|n| n * 2);
+    assert_eq!(*v, 8);
+  }
+
+  #[test]
+  fn consuming() {
+    let x = Lazy::new(|| String::from("x"));
+    let y = x.clone();
+    assert_eq!(y.try_consume(), None);
+    x.seq();
+    let y = x.clone();
+    assert_eq!(y.try_consume(), Some(String::from("x")));
+    assert_eq!(x.consume(), "x");
+    let v = LazyVal::new(|| vec![1]);
+    assert_eq!(v.try_consume(), None);
+    let v = LazyVal::new(|| vec![1]);
+    assert_eq!(v.consume(), vec![1]);
+    // unforced thunks just drop their closures
+    let r = Rc::new(());
+    let rr = r.clone();
+    drop(Lazy::new(move || rr));
+    assert_eq!(Rc::strong_count(&r), 1);
+  }
+
+  #[test]
+  fn iteration() {
+    let x = Lazy::new(|| 7);
+    let mut it = x.clone().into_iter();
+    assert_eq!(it.len(), 1);
+    assert_eq!(it.next(), Some(7));
+    assert_eq!(it.next(), None);
+    assert_eq!(x.into_iter().count(), 1);
+    assert_eq!(Closure::new(|| 1).into_iter().collect::<Vec<_>>(), vec![1]);
+    assert_eq!(LazyVal::new(|| 2).into_iter().last(), Some(2));
+  }
+}
@@ -0,0 +1,386 @@
+// persistent lazy streams
+//
+// each cell is a Lazy, so once a prefix of a stream has been forced it stays forced, and
+// every clone of the stream sees it. this is what makes `Stream::iterate` and friends
+// usable as memo tables.
+
+use std::fmt::{self, Debug, Formatter};
+use std::iter::FromIterator;
+use std::mem;
+use std::rc::Rc;
+
+use kanso_fun::skew::{self, Skew};
+
+use super::{Closure, Lazy};
+
+pub enum Cell<'f, T: 'f> {
+  Nil,
+  Cons(T, Stream<'f, T>),
+}
+
+pub struct Stream<'f, T: 'f>(pub Lazy<'f, Cell<'f, T>>);
+
+// a long forced stream would otherwise be dropped one recursive call per cell. so while
+// we hold the only reference to a forced cell, we take its tail out and drop that next
+impl<'f, T: 'f> Drop for Stream<'f, T> {
+  fn drop(&mut self) {
+    let mut next = take_tail(self);
+    while let Some(mut s) = next {
+      next = take_tail(&mut s)
+    }
+  }
+}
+
+fn take_tail<'f, T: 'f>(s: &mut Stream<'f, T>) -> Option<Stream<'f, T>> {
+  let cell = Rc::get_mut(&mut (s.0).0)?.0.get_mut();
+  if let Closure::Forced(Cell::Cons(..)) = cell {
+    match mem::replace(cell, Closure::Forced(Cell::Nil)) {
+      Closure::Forced(Cell::Cons(_, tail)) => return Some(tail),
+      _ => unreachable!(),
+    }
+  }
+  None
+}
+
+impl<'f, T: 'f> Clone for Stream<'f, T> {
+  fn clone(&self) -> Self {
+    Stream(self.0.clone())
+  }
+}
+
+impl<'f, T: 'f> Stream<'f, T> {
+  pub fn nil() -> Self {
+    Stream(Lazy::new_strict(Cell::Nil))
+  }
+  pub fn cons(head: T, tail: Self) -> Self {
+    Stream(Lazy::new_strict(Cell::Cons(head, tail)))
+  }
+  // a stream whose first cell is computed on demand
+  pub fn new<F: (FnOnce() -> Cell<'f, T>) + 'f>(f: F) -> Self {
+    Stream(Lazy::new(f))
+  }
+
+  pub fn uncons(&self) -> Option<(&T, &Self)> {
+    match self.0.get() {
+      Cell::Nil => None,
+      Cell::Cons(h, t) => Some((h, t)),
+    }
+  }
+  pub fn head(&self) -> Option<&T> {
+    Some(self.uncons()?.0)
+  }
+  pub fn tail(&self) -> Option<&Self> {
+    Some(self.uncons()?.1)
+  }
+  pub fn is_empty(&self) -> bool {
+    self.uncons().is_none()
+  }
+
+  // forces the first n+1 cells
+  pub fn at(&self, mut n: usize) -> Option<&T> {
+    let mut s = self;
+    loop {
+      let (h, t) = s.uncons()?;
+      if n == 0 {
+        return Some(h);
+      }
+      n -= 1;
+      s = t;
+    }
+  }
+
+  // forces the first n cells
+  pub fn drop(&self, mut n: usize) -> Self {
+    let mut s = self;
+    while n > 0 {
+      match s.uncons() {
+        None => break,
+        Some((_, t)) => s = t,
+      }
+      n -= 1;
+    }
+    s.clone()
+  }
+
+  pub fn iter(&self) -> Iter<'_, 'f, T> {
+    Iter(Some(self))
+  }
+
+  pub fn map<'g, U: 'g, F: Fn(&T) -> U + 'g>(&self, f: F) -> Stream<'g, U> where 'f: 'g, T: 'g, {
+    map_rc(self.clone(), Rc::new(f))
+  }
+
+  pub fn zip_with<'g, 'h, U, V, F: Fn(&T, &U) -> V + 'h>(&self, that: &Stream<'g, U>, f: F) -> Stream<'h, V> where
+    'f: 'h, 'g: 'h, T: 'h, U: 'h, V: 'h, {
+    zip_with_rc(self.clone(), that.clone(), Rc::new(f))
+  }
+
+  pub fn unfold<S: 'f, F: Fn(S) -> Option<(T, S)> + 'f>(seed: S, f: F) -> Self {
+    unfold_rc(seed, Rc::new(f))
+  }
+
+  pub fn from_iter_lazy<I: IntoIterator<Item = T>>(iter: I) -> Self where I::IntoIter: 'f, {
+    from_iter_lazy(iter.into_iter())
+  }
+}
+
+impl<'f, T: Clone + 'f> Stream<'f, T> {
+  // an infinite stream of x, f(x), f(f(x)), ...
+  pub fn iterate<F: Fn(&T) -> T + 'f>(x: T, f: F) -> Self {
+    Stream(Lazy::new_strict(iterate_cell(x, Rc::new(f))))
+  }
+
+  pub fn repeat(x: T) -> Self {
+    Stream::iterate(x, T::clone)
+  }
+
+  pub fn take(&self, n: usize) -> Self {
+    let s = self.clone();
+    Stream::new(move || {
+      if n == 0 {
+        return Cell::Nil;
+      }
+      match s.uncons() {
+        None => Cell::Nil,
+        Some((h, t)) => Cell::Cons(h.clone(), t.take(n - 1)),
+      }
+    })
+  }
+
+  // forces cells until it finds one that satisfies p, so filtering an infinite stream
+  // that never does so diverges
+  pub fn filter<P: Fn(&T) -> bool + 'f>(&self, p: P) -> Self {
+    filter_rc(self.clone(), Rc::new(p))
+  }
+
+  // forces the whole stream
+  pub fn to_skew(&self) -> Skew<T> {
+    let xs: Vec<T> = self.iter().cloned().collect();
+    xs.into_iter().rev().fold(skew::nil(), |acc, x| skew::cons(x, acc))
+  }
+}
+
+fn map_rc<'f, 'g, T: 'f, U: 'g, F: Fn(&T) -> U + 'g>(s: Stream<'f, T>, f: Rc<F>) -> Stream<'g, U> where 'f: 'g, {
+  Stream::new(move || match s.uncons() {
+    None => Cell::Nil,
+    Some((h, t)) => Cell::Cons(f(h), map_rc(t.clone(), f.clone())),
+  })
+}
+
+fn zip_with_rc<'f, 'g, 'h, T: 'h, U: 'h, V: 'h, F: Fn(&T, &U) -> V + 'h>(
+  s: Stream<'f, T>,
+  r: Stream<'g, U>,
+  f: Rc<F>,
+) -> Stream<'h, V> where 'f: 'h, 'g: 'h, {
+  Stream::new(move || match (s.uncons(), r.uncons()) {
+    (Some((x, xs)), Some((y, ys))) => Cell::Cons(f(x, y), zip_with_rc(xs.clone(), ys.clone(), f.clone())),
+    _ => Cell::Nil,
+  })
+}
+
+// the next element is only computed when the tail is forced
+fn iterate_cell<'f, T: Clone + 'f, F: Fn(&T) -> T + 'f>(x: T, f: Rc<F>) -> Cell<'f, T> {
+  let y = x.clone();
+  Cell::Cons(x, Stream::new(move || iterate_cell(f(&y), f.clone())))
+}
+
+fn unfold_rc<'f, T: 'f, S: 'f, F: Fn(S) -> Option<(T, S)> + 'f>(seed: S, f: Rc<F>) -> Stream<'f, T> {
+  Stream::new(move || match f(seed) {
+    None => Cell::Nil,
+    Some((x, next)) => Cell::Cons(x, unfold_rc(next, f.clone())),
+  })
+}
+
+fn filter_rc<'f, T: Clone + 'f, P: Fn(&T) -> bool + 'f>(s: Stream<'f, T>, p: Rc<P>) -> Stream<'f, T> {
+  Stream::new(move || {
+    let mut s = s;
+    loop {
+      let next = match s.uncons() {
+        None => return Cell::Nil,
+        Some((h, t)) if p(h) => return Cell::Cons(h.clone(), filter_rc(t.clone(), p.clone())),
+        Some((_, t)) => t.clone(),
+      };
+      s = next;
+    }
+  })
+}
+
+// borrows the elements of a stream, forcing it as it goes
+#[derive(Debug)]
+pub struct Iter<'a, 'f: 'a, T: 'f>(Option<&'a Stream<'f, T>>);
+
+impl<'a, 'f, T: 'f> Iterator for Iter<'a, 'f, T> {
+  type Item = &'a T;
+  fn next(&mut self) -> Option<&'a T> {
+    let (h, t) = self.0?.uncons()?;
+    self.0 = Some(t);
+    Some(h)
+  }
+}
+
+impl<'a, 'f, T: 'f> IntoIterator for &'a Stream<'f, T> {
+  type Item = &'a T;
+  type IntoIter = Iter<'a, 'f, T>;
+  fn into_iter(self) -> Iter<'a, 'f, T> {
+    self.iter()
+  }
+}
+
+// unlike Skew, Stream isn't itself an Iterator, as Iterator::take and friends would
+// shadow the lazy versions above. iterating a stream by value clones its elements
+#[derive(Debug)]
+pub struct IntoIter<'f, T: 'f>(Stream<'f, T>);
+
+impl<'f, T: Clone + 'f> Iterator for IntoIter<'f, T> {
+  type Item = T;
+  fn next(&mut self) -> Option<T> {
+    let (h, t) = {
+      let (h, t) = self.0.uncons()?;
+      (h.clone(), t.clone())
+    };
+    self.0 = t;
+    Some(h)
+  }
+}
+
+impl<'f, T: Clone + 'f> IntoIterator for Stream<'f, T> {
+  type Item = T;
+  type IntoIter = IntoIter<'f, T>;
+  fn into_iter(self) -> IntoIter<'f, T> {
+    IntoIter(self)
+  }
+}
+
+// FromIterator can't require the iterator to outlive 'f, so this buffers it first.
+// use Stream::from_iter_lazy to pull from the iterator as the stream is forced
+impl<'f, T: 'f> FromIterator<T> for Stream<'f, T> {
+  fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+    let xs: Vec<T> = iter.into_iter().collect();
+    Stream::from_iter_lazy(xs)
+  }
+}
+
+fn from_iter_lazy<'f, T: 'f, I: Iterator<Item = T> + 'f>(mut it: I) -> Stream<'f, T> {
+  Stream::new(move || match it.next() {
+    None => Cell::Nil,
+    Some(x) => Cell::Cons(x, from_iter_lazy(it)),
+  })
+}
+
+// lazily unconses the skew list
+impl<'f, T: Clone + 'f> From<Skew<T>> for Stream<'f, T> {
+  fn from(xs: Skew<T>) -> Self {
+    Stream::new(move || match xs.uncons() {
+      None => Cell::Nil,
+      Some((h, t)) => Cell::Cons(h.clone(), Stream::from(t)),
+    })
+  }
+}
+
+impl<'f, T: 'f> Default for Stream<'f, T> {
+  fn default() -> Self {
+    Stream::nil()
+  }
+}
+
+// shows the forced prefix
+impl<'f, T: Debug + 'f> Debug for Stream<'f, T> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    let mut l = f.debug_list();
+    let mut s = self;
+    loop {
+      match s.0.try_get() {
+        None => {
+          l.entry(&format_args!("..."));
+          break;
+        }
+        Some(Cell::Nil) => break,
+        Some(Cell::Cons(h, t)) => {
+          l.entry(h);
+          s = t;
+        }
+      }
+    }
+    l.finish()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::cell::Cell as StdCell;
+
+  #[test]
+  fn memoized() {
+    let calls = Rc::new(StdCell::new(0));
+    let c = calls.clone();
+    let nats = Stream::iterate(0u64, move |n| {
+      c.set(c.get() + 1);
+      n + 1
+    });
+    assert_eq!(calls.get(), 0);
+    assert_eq!(nats.at(10), Some(&10));
+    assert_eq!(calls.get(), 10);
+    // a clone shares the forced prefix
+    let again = nats.clone();
+    assert_eq!(again.at(10), Some(&10));
+    assert_eq!(calls.get(), 10);
+    assert_eq!(format!("{:?}", nats.take(3)), "[...]");
+  }
+
+  #[test]
+  fn long_drop() {
+    let nats = Stream::iterate(0u64, |n| n + 1);
+    assert_eq!(nats.at(1_000_000), Some(&1_000_000));
+    drop(nats);
+    // and with the forced part shared, which has to be left alone until its last handle goes
+    let nats = Stream::iterate(0u64, |n| n + 1);
+    let rest = nats.drop(500_000);
+    assert_eq!(nats.at(1_000_000), Some(&1_000_000));
+    drop(nats);
+    assert_eq!(rest.head(), Some(&500_000));
+    drop(rest);
+  }
+
+  #[test]
+  fn fibs() {
+    let fibs = Stream::unfold((0u64, 1u64), |(a, b)| Some((a, (b, a + b))));
+    let xs: Vec<u64> = fibs.take(10).into_iter().collect();
+    assert_eq!(xs, vec![0, 1, 1, 2, 3, 5, 8, 13, 21, 34]);
+    let evens: Vec<u64> = fibs.filter(|n| n % 2 == 0).take(4).into_iter().collect();
+    assert_eq!(evens, vec![0, 2, 8, 34]);
+  }
+
+  #[test]
+  fn combinators() {
+    let nats = Stream::iterate(1u32, |n| n + 1);
+    let squares = nats.map(|n| n * n);
+    let sums = nats.zip_with(&squares, |a, b| a + b);
+    assert_eq!(sums.take(4).into_iter().collect::<Vec<_>>(), vec![2, 6, 12, 20]);
+    assert_eq!(nats.drop(5).head(), Some(&6));
+    assert_eq!(Stream::repeat('x').take(3).into_iter().collect::<String>(), "xxx");
+    let s: Stream<u32> = vec![1, 2, 3].into_iter().collect();
+    assert_eq!(s.iter().sum::<u32>(), 6);
+    assert!(s.drop(3).is_empty());
+    assert_eq!(s.at(3), None);
+    assert_eq!(format!("{:?}", s), "[1, 2, 3]");
+  }
+
+  #[test]
+  fn from_iter_lazy() {
+    let pulled = Rc::new(StdCell::new(0));
+    let p = pulled.clone();
+    let s = Stream::from_iter_lazy((0..).inspect(move |_| p.set(p.get() + 1)));
+    assert_eq!(s.at(2), Some(&2));
+    assert_eq!(pulled.get(), 3);
+  }
+
+  #[test]
+  fn skew() {
+    let xs = (0..20).rev().fold(skew::nil(), |acc, x| skew::cons(x, acc));
+    let s = Stream::from(xs.clone());
+    assert_eq!(s.at(13), Some(&13));
+    assert_eq!(s.to_skew(), xs);
+    assert_eq!(Stream::iterate(0u32, |n| n + 1).take(20).to_skew(), xs);
+  }
+}
@@ -0,0 +1,427 @@
+// thread-safe thunks
+//
+// a forcing thread records itself as the owner of the thunk while it runs the closure,
+// other threads block until it is done rather than evaluating it twice. forcing a thunk
+// that the current thread is already forcing is a cycle, as is waiting on a thread that
+// is (transitively) waiting on us, and both are reported as LazyError::Cycle rather than
+// deadlocking. if the closure panics, the panic propagates to the forcing thread and the
+// thunk is left poisoned, so everyone else gets LazyError::Poisoned.
+
+use std::borrow::Borrow;
+use std::boxed::Box;
+use std::cell::UnsafeCell;
+use std::fmt::{self, Debug, Formatter};
+use std::mem;
+use std::ops::Deref;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, ThreadId};
+
+use super::LazyError;
+
+enum State<'f, T> {
+  Delayed(Box<dyn (FnOnce() -> T) + Send + 'f>),
+  Forcing(ThreadId),
+  Forced,
+  Poisoned,
+}
+
+// we never panic while holding one of our own locks, so poisoning carries no information
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+  m.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// a thread blocked on a thunk being forced by another thread
+struct Wait {
+  waiter: ThreadId,
+  owner: ThreadId,
+  thunk: usize,
+}
+
+static WAITING: Mutex<Vec<Wait>> = Mutex::new(Vec::new());
+
+// record that me is about to wait on owner, unless that would close a cycle
+fn start_waiting(me: ThreadId, owner: ThreadId, thunk: usize) -> bool {
+  let mut waiting = lock(&WAITING);
+  let mut t = owner;
+  loop {
+    if t == me {
+      return false;
+    }
+    match waiting.iter().find(|w| w.waiter == t) {
+      Some(w) => t = w.owner,
+      None => break,
+    }
+  }
+  waiting.push(Wait { waiter: me, owner, thunk });
+  true
+}
+
+fn stop_waiting(me: ThreadId) {
+  lock(&WAITING).retain(|w| w.waiter != me)
+}
+
+// called by the owner before it wakes the waiters, so the wait graph never has stale edges
+fn release(thunk: usize) {
+  lock(&WAITING).retain(|w| w.thunk != thunk)
+}
+
+// a thread-safe scala-style 'lazy val'
+pub struct SyncLazyVal<'f, T> {
+  forced: AtomicBool,
+  state: Mutex<State<'f, T>>,
+  cond: Condvar,
+  // written once by the owner before forced is set, never mutated again
+  value: UnsafeCell<Option<T>>,
+}
+
+unsafe impl<'f, T: Send + Sync> Sync for SyncLazyVal<'f, T> {}
+
+impl<'f, T: 'f> SyncLazyVal<'f, T> {
+  pub fn new<F: (FnOnce() -> T) + Send + 'f>(f: F) -> Self {
+    SyncLazyVal {
+      forced: AtomicBool::new(false),
+      state: Mutex::new(State::Delayed(Box::new(f))),
+      cond: Condvar::new(),
+      value: UnsafeCell::new(None),
+    }
+  }
+
+  #[inline]
+  fn addr(&self) -> usize {
+    self as *const Self as usize
+  }
+
+  #[inline]
+  pub fn ready(&self) -> bool {
+    self.forced.load(Ordering::Acquire)
+  }
+
+  #[inline]
+  pub fn try_get(&self) -> Option<&T> {
+    if self.ready() {
+      unsafe { &*self.value.get() }.as_ref()
+    } else {
+      None
+    }
+  }
+
+  pub fn is_poisoned(&self) -> bool {
+    matches!(*lock(&self.state), State::Poisoned)
+  }
+
+  pub fn get_checked(&self) -> Result<&T, LazyError> {
+    if let Some(t) = self.try_get() {
+      return Ok(t);
+    }
+    let me = thread::current().id();
+    let mut st = lock(&self.state);
+    loop {
+      match *st {
+        State::Forced => break,
+        State::Poisoned => return Err(LazyError::Poisoned),
+        State::Forcing(owner) => {
+          if owner == me || !start_waiting(me, owner, self.addr()) {
+            return Err(LazyError::Cycle);
+          }
+          st = self.cond.wait(st).unwrap_or_else(PoisonError::into_inner);
+          stop_waiting(me);
+        }
+        State::Delayed(_) => {
+          let f = match mem::replace(&mut *st, State::Forcing(me)) {
+            State::Delayed(f) => f,
+            _ => unreachable!(),
+          };
+          drop(st);
+          let result = panic::catch_unwind(AssertUnwindSafe(f));
+          st = lock(&self.state);
+          release(self.addr());
+          match result {
+            Ok(t) => {
+              unsafe { *self.value.get() = Some(t) };
+              self.forced.store(true, Ordering::Release);
+              *st = State::Forced;
+              self.cond.notify_all();
+              break;
+            }
+            Err(e) => {
+              *st = State::Poisoned;
+              self.cond.notify_all();
+              drop(st);
+              panic::resume_unwind(e)
+            }
+          }
+        }
+      }
+    }
+    drop(st);
+    Ok(unsafe { &*self.value.get() }.as_ref().unwrap())
+  }
+
+  pub fn try_force(&self) -> Result<(), LazyError> {
+    self.get_checked().map(|_| ())
+  }
+
+  pub fn get(&self) -> &T {
+    match self.get_checked() {
+      Ok(t) => t,
+      Err(e) => panic!("{}", e),
+    }
+  }
+
+  pub fn seq(&self) {
+    self.get();
+  }
+
+  pub fn consume(self) -> T {
+    match self.state.into_inner().unwrap_or_else(PoisonError::into_inner) {
+      State::Delayed(f) => f(),
+      State::Forced => self.value.into_inner().unwrap(),
+      State::Poisoned => panic!("{}", LazyError::Poisoned),
+      State::Forcing(_) => unreachable!(),
+    }
+  }
+
+  pub fn try_consume(self) -> Option<T> {
+    self.value.into_inner()
+  }
+}
+
+impl<'f, T: Default + 'f> Default for SyncLazyVal<'f, T> {
+  fn default() -> Self {
+    SyncLazyVal::new(|| T::default())
+  }
+}
+
+impl<'f, T: 'f> From<T> for SyncLazyVal<'f, T> {
+  fn from(that: T) -> Self {
+    SyncLazyVal {
+      forced: AtomicBool::new(true),
+      state: Mutex::new(State::Forced),
+      cond: Condvar::new(),
+      value: UnsafeCell::new(Some(that)),
+    }
+  }
+}
+
+impl<'f, T: Debug + 'f> Debug for SyncLazyVal<'f, T> {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    if let Some(t) = self.try_get() {
+      return Debug::fmt(t, f);
+    }
+    match *lock(&self.state) {
+      State::Poisoned => f.write_str("<poisoned>"),
+      State::Forcing(_) => f.write_str("<forcing>"),
+      _ => f.write_str("<closure>"),
+    }
+  }
+}
+
+impl<'f, T: 'f> Borrow<T> for SyncLazyVal<'f, T> {
+  fn borrow(&self) -> &T {
+    self.get()
+  }
+}
+
+impl<'f, T: 'f> AsRef<T> for SyncLazyVal<'f, T> {
+  fn as_ref(&self) -> &T {
+    self.get()
+  }
+}
+
+impl<'f, T: 'f> Deref for SyncLazyVal<'f, T> {
+  type Target = T;
+  fn deref(&self) -> &T {
+    self.get()
+  }
+}
+
+// a haskell-style thunk that can be shared between threads
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct SyncLazy<'f, T: 'f>(pub Arc<SyncLazyVal<'f, T>>);
+
+impl<'f, T: 'f> Clone for SyncLazy<'f, T> {
+  fn clone(&self) -> Self {
+    SyncLazy(self.0.clone())
+  }
+
+  fn clone_from(&mut self, source: &Self) {
+    self.0.clone_from(&source.0)
+  }
+}
+
+impl<'f, T: 'f> SyncLazy<'f, T> {
+  pub fn new<F: (FnOnce() -> T) + Send + 'f>(f: F) -> Self {
+    SyncLazy(Arc::new(SyncLazyVal::new(f)))
+  }
+  pub fn new_strict(value: T) -> Self {
+    SyncLazy(Arc::new(SyncLazyVal::from(value)))
+  }
+  pub fn seq(&self) {
+    self.0.seq()
+  }
+  pub fn ready(&self) -> bool {
+    self.0.ready()
+  }
+  pub fn is_poisoned(&self) -> bool {
+    self.0.is_poisoned()
+  }
+  pub fn get(&self) -> &T {
+    self.0.get()
+  }
+  pub fn try_force(&self) -> Result<(), LazyError> {
+    self.0.try_force()
+  }
+  pub fn get_checked(&self) -> Result<&T, LazyError> {
+    self.0.get_checked()
+  }
+  pub fn try_get(&self) -> Option<&T> {
+    self.0.try_get()
+  }
+  pub fn map<'g, U, F: (FnOnce(&T) -> U) + Send + 'g>(&self, f: F) -> SyncLazy<'g, U> where
+    'f: 'g, T: Send + Sync + 'g, U: 'g, {
+    let me = self.clone();
+    SyncLazy::new(move || f(me.get()))
+  }
+  pub fn map2<'g, 'h, U, V, F: (FnOnce(&T, &U) -> V) + Send + 'h>(
+    this: &SyncLazy<'f, T>,
+    that: &SyncLazy<'g, U>,
+    f: F,
+  ) -> SyncLazy<'h, V> where
+    'f: 'h, 'g: 'h, T: Send + Sync + 'h, U: Send + Sync + 'h, {
+    let a = this.clone();
+    let b = that.clone();
+    SyncLazy::new(move || f(a.get(), b.get()))
+  }
+
+  // consumes this lazy value in an effort to try to avoid cloning the contents
+  pub fn consume(self) -> T where T: Clone, {
+    match Arc::try_unwrap(self.0) {
+      Result::Ok(lval) => lval.consume(),
+      Result::Err(this) => this.get().clone(),
+    }
+  }
+  pub fn try_consume(self) -> Option<T> where T: Clone, {
+    match Arc::try_unwrap(self.0) {
+      Result::Ok(lval) => lval.try_consume(),
+      Result::Err(this) => Some(this.try_get()?.clone()),
+    }
+  }
+}
+
+impl<'f, T: 'f + Default> Default for SyncLazy<'f, T> {
+  fn default() -> Self {
+    SyncLazy::new(|| T::default())
+  }
+}
+
+impl<'f, T: 'f> From<T> for SyncLazy<'f, T> {
+  #[inline]
+  fn from(that: T) -> Self {
+    SyncLazy::new_strict(that)
+  }
+}
+
+impl<'f, T: 'f> Borrow<T> for SyncLazy<'f, T> {
+  fn borrow(&self) -> &T {
+    self.get()
+  }
+}
+
+impl<'f, T: 'f> AsRef<T> for SyncLazy<'f, T> {
+  fn as_ref(&self) -> &T {
+    self.get()
+  }
+}
+
+impl<'f, T: 'f> Deref for SyncLazy<'f, T> {
+  type Target = T;
+  fn deref(&self) -> &T {
+    self.get()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::atomic::AtomicUsize;
+  use std::sync::Barrier;
+
+  #[test]
+  fn forced_once() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let c = count.clone();
+    let x = SyncLazy::new(move || {
+      c.fetch_add(1, Ordering::SeqCst);
+      thread::sleep(std::time::Duration::from_millis(10));
+      42
+    });
+    let y = x.map(|r| r + 1);
+    let ts: Vec<_> = (0..8).map(|_| {
+      let x = x.clone();
+      let y = y.clone();
+      thread::spawn(move || *x.get() + *y.get())
+    }).collect();
+    for t in ts {
+      assert_eq!(t.join().unwrap(), 85);
+    }
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+    assert_eq!(x.consume(), 42);
+  }
+
+  #[test]
+  fn reentrant_cycle() {
+    let me: Arc<Mutex<Option<SyncLazy<'static, u32>>>> = Arc::new(Mutex::new(None));
+    let m = me.clone();
+    let x = SyncLazy::new(move || {
+      let this = m.lock().unwrap().clone().unwrap();
+      match this.get_checked() {
+        Err(LazyError::Cycle) => 0,
+        _ => 1,
+      }
+    });
+    *me.lock().unwrap() = Some(x.clone());
+    assert_eq!(x.get_checked(), Ok(&0));
+    *me.lock().unwrap() = None;
+  }
+
+  #[test]
+  fn cycle_across_threads() {
+    let barrier = Arc::new(Barrier::new(2));
+    type Pair = (SyncLazy<'static, u32>, SyncLazy<'static, u32>);
+    let cell: Arc<Mutex<Option<Pair>>> = Arc::new(Mutex::new(None));
+    let mk = |i: usize| {
+      let barrier = barrier.clone();
+      let cell = cell.clone();
+      SyncLazy::new(move || {
+        barrier.wait();
+        let pair = cell.lock().unwrap().clone().unwrap();
+        let other = if i == 0 { pair.1 } else { pair.0 };
+        match other.get_checked() {
+          Ok(n) => n + 1,
+          Err(LazyError::Cycle) => 0,
+          Err(LazyError::Poisoned) => 100,
+        }
+      })
+    };
+    let a = mk(0);
+    let b = mk(1);
+    *cell.lock().unwrap() = Some((a.clone(), b.clone()));
+    let ta = { let a = a.clone(); thread::spawn(move || *a.get()) };
+    let tb = { let b = b.clone(); thread::spawn(move || *b.get()) };
+    let (x, y) = (ta.join().unwrap(), tb.join().unwrap());
+    assert_eq!(x + y, 1);
+    *cell.lock().unwrap() = None;
+  }
+
+  #[test]
+  fn poisoned() {
+    let x: SyncLazy<'static, u32> = SyncLazy::new(|| panic!("boom"));
+    let y = x.clone();
+    assert!(thread::spawn(move || *y.get()).join().is_err());
+    assert!(x.is_poisoned());
+    assert_eq!(x.get_checked(), Err(LazyError::Poisoned));
+  }
+}
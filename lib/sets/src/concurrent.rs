@@ -0,0 +1,274 @@
+use std::mem::swap;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+
+use meta::Meta;
+
+// Rem's algorithm again, but with the parent links updated by compare and swap so that
+// find, same and union can all run from many threads at once without locks.
+//
+// a link is only ever made from a root to a larger Meta, and path halving only moves a
+// link further up the same path, so a failed compare and swap just means someone else made
+// progress and we can reread and carry on.
+//
+// the parents live in buckets that double in size, rather than one Vec<AtomicU32>, so
+// make_set can grow the structure through a shared reference without moving the slots out
+// from under other threads.
+
+const BUCKETS: usize = 32;
+
+pub struct ConcurrentSets {
+  // bucket b holds the parents of indices 2^b - 1 .. 2^(b+1) - 1
+  buckets: [AtomicPtr<AtomicU32>; BUCKETS],
+  len: AtomicU32,
+}
+
+#[inline]
+fn locate(i: u32) -> (usize, usize) {
+  let j = i as u64 + 1;
+  let b = 63 - j.leading_zeros() as usize;
+  (b, (j - (1 << b)) as usize)
+}
+
+impl ConcurrentSets {
+  pub fn new() -> Self {
+    ConcurrentSets {
+      buckets: Default::default(),
+      len: AtomicU32::new(0),
+    }
+  }
+
+  pub fn with_capacity(capacity: u32) -> Self {
+    let sets = Self::new();
+    if capacity > 0 {
+      let (last, _) = locate(capacity - 1);
+      for b in 0..=last {
+        sets.bucket(b);
+      }
+    }
+    sets
+  }
+
+  // every parent in a fresh bucket points at itself, so make_set has nothing to write
+  fn bucket(&self, b: usize) -> *const AtomicU32 {
+    let p = self.buckets[b].load(Ordering::Acquire);
+    if !p.is_null() {
+      return p;
+    }
+    let base = (1u64 << b) - 1;
+    let fresh: Box<[AtomicU32]> = (0..1u64 << b).map(|j| AtomicU32::new((base + j) as u32)).collect();
+    let fresh = Box::into_raw(fresh) as *mut AtomicU32;
+    match self.buckets[b].compare_exchange(ptr::null_mut(), fresh, Ordering::AcqRel, Ordering::Acquire) {
+      Ok(_) => fresh,
+      Err(winner) => {
+        // someone else got there first
+        unsafe { drop(Box::from_raw(ptr::slice_from_raw_parts_mut(fresh, 1 << b))) };
+        winner
+      }
+    }
+  }
+
+  fn slot(&self, p: Meta) -> &AtomicU32 {
+    let (b, offset) = locate(p.u32());
+    let bucket = self.buckets[b].load(Ordering::Acquire);
+    assert!(!bucket.is_null() && p.u32() < self.len(), "unknown meta {:?}", p);
+    unsafe { &*bucket.add(offset) }
+  }
+
+  pub fn make_set(&self) -> Meta {
+    let i = self.len.fetch_add(1, Ordering::Relaxed);
+    // u32::MAX is Meta's niche
+    assert!(i < u32::MAX, "too many sets");
+    self.bucket(locate(i).0);
+    unsafe { Meta::new_unchecked(i) }
+  }
+
+  // includes sets still being made by other threads
+  pub fn len(&self) -> u32 { self.len.load(Ordering::Relaxed) }
+
+  pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+  pub fn parent(&self, p: Meta) -> Meta {
+    unsafe { Meta::new_unchecked(self.slot(p).load(Ordering::Acquire)) }
+  }
+
+  fn cas_parent(&self, p: Meta, old: Meta, new: Meta) -> bool {
+    self.slot(p).compare_exchange(old.u32(), new.u32(), Ordering::AcqRel, Ordering::Acquire).is_ok()
+  }
+
+  // find with path halving
+  pub fn find(&self, mut p: Meta) -> Meta {
+    loop {
+      let pp = self.parent(p);
+      if p == pp {
+        break p
+      }
+      let gp = self.parent(pp);
+      if pp != gp {
+        self.cas_parent(p, pp, gp);
+      }
+      p = pp
+    }
+  }
+
+  // make them equal and returns the first node at which this becomes true
+  pub fn union(&self, mut u: Meta, mut v: Meta) -> Meta {
+    loop {
+      let mut up = self.parent(u);
+      let mut vp = self.parent(v);
+      if u == v || up == vp {
+        break up
+      }
+      if vp < up {
+        swap(&mut u, &mut v);
+        swap(&mut up, &mut vp);
+      }
+      if u == up {
+        if self.cas_parent(u, u, vp) {
+          break vp
+        }
+        // u stopped being a root under us
+        continue
+      }
+      let z = self.parent(up);
+      if up != z {
+        self.cas_parent(u, up, z);
+      }
+      u = up;
+    }
+  }
+
+  pub fn union_find(&self, u: Meta, v: Meta) -> Meta {
+    let w = self.union(u, v);
+    self.find(w)
+  }
+
+  // false only if u and v were in different sets at some point during the call
+  pub fn same(&self, mut u: Meta, mut v: Meta) -> bool {
+    loop {
+      let mut up = self.parent(u);
+      let mut vp = self.parent(v);
+      if u == v || up == vp {
+        break true
+      }
+      if vp < up {
+        swap(&mut u, &mut v);
+        swap(&mut up, &mut vp);
+      }
+      // u is a root smaller than anything on v's path, so it can't be v's root
+      if u == up {
+        break false
+      }
+      let z = self.parent(up);
+      if up != z {
+        self.cas_parent(u, up, z);
+      }
+      u = up;
+    }
+  }
+}
+
+impl Default for ConcurrentSets {
+  fn default() -> Self { Self::new() }
+}
+
+impl Drop for ConcurrentSets {
+  fn drop(&mut self) {
+    for (b, bucket) in self.buckets.iter_mut().enumerate() {
+      let p = *bucket.get_mut();
+      if !p.is_null() {
+        unsafe { drop(Box::from_raw(ptr::slice_from_raw_parts_mut(p, 1 << b))) }
+      }
+    }
+  }
+}
+
+impl std::fmt::Debug for ConcurrentSets {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_list().entries((0..self.len()).map(|i| self.parent(unsafe { Meta::new_unchecked(i) }))).finish()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testing::Rng;
+  use std::sync::Barrier;
+  use std::thread;
+  use Sets;
+
+  #[test]
+  fn it_works() {
+    let ds = ConcurrentSets::with_capacity(2);
+    let x = ds.make_set();
+    let y = ds.make_set();
+    let z = ds.make_set();
+    assert!(ds.same(x, x));
+    assert!(!ds.same(x, y));
+    ds.union(x, y);
+    assert!(ds.same(x, y));
+    assert!(!ds.same(x, z));
+    ds.union(x, z);
+    assert!(ds.same(y, z));
+    assert_eq!(ds.len(), 3);
+    assert_eq!(ds.find(x), ds.find(z));
+  }
+
+  #[test]
+  fn stress() {
+    const THREADS: usize = 8;
+    const SETS: u32 = 4000;
+    const UNIONS: usize = 3000;
+    let ds = ConcurrentSets::new();
+    let barrier = Barrier::new(THREADS);
+    // every thread makes some of the sets, then they all union at once
+    let unions: Vec<Vec<(Meta, Meta)>> = thread::scope(|s| {
+      let handles: Vec<_> = (0..THREADS)
+        .map(|t| {
+          let (ds, barrier) = (&ds, &barrier);
+          s.spawn(move || {
+            for _ in 0..SETS as usize / THREADS {
+              ds.make_set();
+            }
+            barrier.wait();
+            let mut rng = Rng::new(t as u64 + 1);
+            let pairs: Vec<(Meta, Meta)> = (0..UNIONS).map(|_| (rng.meta(SETS), rng.meta(SETS))).collect();
+            for &(u, v) in &pairs {
+              ds.union(u, v);
+              assert!(ds.same(u, v));
+            }
+            pairs
+          })
+        })
+        .collect();
+      handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    let mut sequential = Sets::with_capacity(SETS);
+    for _ in 0..SETS {
+      sequential.make_set();
+    }
+    for &(u, v) in unions.iter().flatten() {
+      sequential.union(u, v);
+    }
+    for i in 0..SETS {
+      let u = Meta::new(i).unwrap();
+      let v = Meta::new((i * 7919) % SETS).unwrap();
+      assert_eq!(ds.same(u, v), sequential.same(u, v), "{:?} {:?}", u, v);
+    }
+  }
+
+  #[test]
+  fn grows_concurrently() {
+    let ds = ConcurrentSets::new();
+    let made: Vec<Vec<Meta>> = thread::scope(|s| {
+      let handles: Vec<_> = (0..8).map(|_| s.spawn(|| (0..1000).map(|_| ds.make_set()).collect())).collect();
+      handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    let mut all: Vec<u32> = made.iter().flatten().map(|m| m.u32()).collect();
+    all.sort_unstable();
+    assert_eq!(all, (0..8000).collect::<Vec<_>>());
+    for m in made.iter().flatten() {
+      assert_eq!(ds.find(*m), *m);
+    }
+  }
+}
@@ -1,7 +1,23 @@
+extern crate kanso_fun;
+
 use std::mem::swap;
 use std::convert::TryFrom;
 
-use meta::Meta;
+pub mod meta;
+pub mod concurrent;
+pub mod undo;
+pub mod persistent;
+pub mod with;
+pub mod linking;
+pub mod proof;
+
+pub use meta::Meta;
+pub use concurrent::ConcurrentSets;
+pub use undo::{Snapshot, UndoSets};
+pub use persistent::PersistentSets;
+pub use with::SetsWith;
+pub use linking::{Linking, WeightedSets};
+pub use proof::{ProofSets, Step};
 
 // Using Rem's algorithm rather than the standard Tarjan tricks.
 // https://drops.dagstuhl.de/opus/volltexte/2020/11801/pdf/LIPIcs-OPODIS-2019-15.pdf
@@ -35,8 +51,6 @@ impl Sets {
 
   pub fn parent(&self, p: Meta) -> Meta { self.0[usize::from(p)] }
 
-  fn parent_mut(&mut self, p: Meta) -> &mut Meta { &mut self.0[usize::from(p)]}
-
   // find without self-modification
   pub fn find(&self, mut p: Meta) -> Meta {
     while p != self.parent(p) {
@@ -45,20 +59,45 @@ impl Sets {
     p
   }
 
-  pub fn find_mut(&mut self, mut p: Meta) -> Meta {
-    while p != self.parent(p) {
-      let gp = self.parent(self.parent(p));
-      *self.parent_mut(p) = gp;
+  pub fn find_mut(&mut self, p: Meta) -> Meta { Links::find_mut(self, p) }
+
+  // make them equal and returns the first node at which this becomes true
+  pub fn union(&mut self, u: Meta, v: Meta) -> Meta { Links::union(self, u, v) }
+
+  // match the behavior of a more traditional union_find
+  pub fn union_find(&mut self, u: Meta, v: Meta) -> Meta {
+    let w = self.union(u,v);
+    self.find_mut(w)
+  }
+
+  pub fn same(&mut self, u: Meta, v: Meta) -> bool { Links::same(self, u, v) }
+}
+
+impl Links for Sets {
+  fn link(&self, p: Meta) -> Meta { self.parent(p) }
+  fn set_link(&mut self, p: Meta, q: Meta) { self.0[usize::from(p)] = q }
+}
+
+// the algorithms, over whatever stores the parent links. UndoSets logs each write so it
+// can be undone, PersistentSets keeps the old version around, and WeightedSets uses them
+// when linking by index. see ConcurrentSets for the multithreaded version of these updates
+trait Links {
+  fn link(&self, p: Meta) -> Meta;
+  fn set_link(&mut self, p: Meta, q: Meta);
+
+  fn find_mut(&mut self, mut p: Meta) -> Meta {
+    while p != self.link(p) {
+      let gp = self.link(self.link(p));
+      self.set_link(p, gp);
       p = gp
     }
     p
   }
 
-  // make them equal and returns the first node at which this becomes true
-  pub fn union(&mut self, mut u: Meta, mut v: Meta) -> Meta {
+  fn union(&mut self, mut u: Meta, mut v: Meta) -> Meta {
     loop {
-      let mut up = self.parent(u);
-      let mut vp = self.parent(v);
+      let mut up = self.link(u);
+      let mut vp = self.link(v);
       if u == v || up == vp { 
         break up 
       }
@@ -67,31 +106,22 @@ impl Sets {
         swap(&mut up,&mut vp);
       }
       if u == up {
-        // if we're doing this multithreaded then
-        // if compare_and_swap(self.parent_mut(u),u,vp) { return vp; } 
-        *self.parent_mut(u) = vp;
+        self.set_link(u, vp);
         break vp;
       }
-      v = self.parent(up);
-      if up != v {
-        // if we're doing this multithreaded then
-        // compare_and_swap(self.parent_mut(u), up, v)
-        *self.parent_mut(u) = v;
+      // z, not v: v is still needed on the next pass
+      let z = self.link(up);
+      if up != z {
+        self.set_link(u, z);
       }
       u = up;
     }
   }
 
-  // match the behavior of a more traditional union_find
-  pub fn union_find(&mut self, u: Meta, v: Meta) -> Meta {
-    let w = self.union(u,v);
-    self.find_mut(w)
-  }
-
-  pub fn same(&mut self, mut u: Meta, mut v: Meta) -> bool {
+  fn same(&mut self, mut u: Meta, mut v: Meta) -> bool {
     loop { 
-      let mut up = self.parent(u);
-      let mut vp = self.parent(v);
+      let mut up = self.link(u);
+      let mut vp = self.link(v);
       if u == v || up == vp {
         break true
       }
@@ -102,16 +132,50 @@ impl Sets {
       if u == up { 
         break false
       }
-      v = self.parent(up);
-      if up != v {
-        // compare_and_swap(self.parent_mut(u), up, v)
-        *self.parent_mut(u) = v;
+      let z = self.link(up);
+      if up != z {
+        self.set_link(u, z);
       }
       u = up;
     }
   }
 }
 
+// shared by the tests of every kind of sets
+#[cfg(test)]
+mod testing {
+  use Meta;
+
+  // a cheap deterministic generator, xorshift, so every run checks the same operations
+  pub struct Rng(u64);
+
+  impl Rng {
+    pub fn new(seed: u64) -> Self { Rng(seed) }
+
+    pub fn below(&mut self, n: u64) -> u64 {
+      self.0 ^= self.0 << 13;
+      self.0 ^= self.0 >> 7;
+      self.0 ^= self.0 << 17;
+      self.0 % n
+    }
+
+    // one of the first n metas
+    pub fn meta(&mut self, n: u32) -> Meta { Meta::new(self.below(u64::from(n)) as u32).unwrap() }
+  }
+
+  // a naive model, one label per set, to compare against
+  pub fn label(labels: &[u32], m: Meta) -> u32 { labels[usize::from(m)] }
+
+  pub fn merge(labels: &mut [u32], u: Meta, v: Meta) {
+    let (a, b) = (label(labels, u), label(labels, v));
+    for l in labels.iter_mut() {
+      if *l == b {
+        *l = a
+      }
+    }
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -141,4 +205,18 @@ mod tests {
     let vr = ds.find_mut(v);
     assert_eq!(w,vr); // known to be roots
   }
+
+  #[test]
+  fn union_below_a_root() {
+    let mut ds = Sets::new();
+    let a = ds.make_set();
+    let b = ds.make_set();
+    let c = ds.make_set();
+    ds.union(c,b);
+    // c is no longer a root, and its parent outranks a
+    assert!(!ds.same(c,a));
+    ds.union(c,a);
+    assert!(ds.same(a,b));
+    assert!(ds.same(a,c));
+  }
 }
@@ -0,0 +1,172 @@
+use std::convert::TryFrom;
+
+use {Links, Meta};
+
+// the textbook ways of choosing which root to link under the other, to benchmark against
+// the index order that Sets uses. unlike Rem's algorithm these have to find both roots
+// before they can link them, and they need a weight per set.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Linking {
+  // Rem's algorithm, as in Sets. the weights go unused
+  #[default]
+  Index,
+  // the smaller class goes under the larger
+  Size,
+  // the shallower tree goes under the deeper, by an upper bound on its height
+  Rank,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WeightedSets {
+  parents: Vec<Meta>,
+  // the size or rank of each root, meaningless elsewhere
+  weights: Vec<u32>,
+  linking: Linking,
+}
+
+impl WeightedSets {
+  pub fn with_capacity(linking: Linking, capacity: u32) -> Self {
+    let capacity = usize::try_from(capacity).unwrap();
+    WeightedSets { parents: Vec::with_capacity(capacity), weights: Vec::with_capacity(capacity), linking }
+  }
+
+  pub fn new(linking: Linking) -> Self { WeightedSets { parents: Vec::new(), weights: Vec::new(), linking } }
+
+  pub fn linking(&self) -> Linking { self.linking }
+
+  pub fn make_set(&mut self) -> Meta {
+    let id = unsafe { Meta::new_unchecked(self.len()) };
+    self.parents.push(id);
+    self.weights.push(if self.linking == Linking::Size { 1 } else { 0 });
+    id
+  }
+
+  pub fn len(&self) -> u32 { u32::try_from(self.parents.len()).unwrap() }
+
+  pub fn is_empty(&self) -> bool { self.parents.is_empty() }
+
+  pub fn parent(&self, p: Meta) -> Meta { self.parents[usize::from(p)] }
+
+  // find without self-modification
+  pub fn find(&self, mut p: Meta) -> Meta {
+    while p != self.parent(p) {
+      p = self.parent(p);
+    }
+    p
+  }
+
+  pub fn find_mut(&mut self, p: Meta) -> Meta { Links::find_mut(self, p) }
+
+  // make them equal. with Linking::Index this returns the first node at which that became
+  // true, as Sets does, otherwise the new root
+  pub fn union(&mut self, u: Meta, v: Meta) -> Meta {
+    if self.linking == Linking::Index {
+      return Links::union(self, u, v)
+    }
+    let ru = self.find_mut(u);
+    let rv = self.find_mut(v);
+    if ru == rv {
+      return ru
+    }
+    let (wu, wv) = (self.weights[usize::from(ru)], self.weights[usize::from(rv)]);
+    let (child, root) = if wu < wv { (ru, rv) } else { (rv, ru) };
+    self.parents[usize::from(child)] = root;
+    match self.linking {
+      Linking::Size => self.weights[usize::from(root)] = wu + wv,
+      Linking::Rank if wu == wv => self.weights[usize::from(root)] += 1,
+      _ => {}
+    }
+    root
+  }
+
+  pub fn union_find(&mut self, u: Meta, v: Meta) -> Meta {
+    let w = self.union(u,v);
+    self.find_mut(w)
+  }
+
+  pub fn same(&mut self, u: Meta, v: Meta) -> bool {
+    if self.linking == Linking::Index {
+      Links::same(self, u, v)
+    } else {
+      self.find_mut(u) == self.find_mut(v)
+    }
+  }
+}
+
+impl Links for WeightedSets {
+  fn link(&self, p: Meta) -> Meta { self.parent(p) }
+  fn set_link(&mut self, p: Meta, q: Meta) { self.parents[usize::from(p)] = q }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testing::Rng;
+
+  const LINKINGS: [Linking; 3] = [Linking::Index, Linking::Size, Linking::Rank];
+
+  #[test]
+  fn it_works() {
+    for &linking in &LINKINGS {
+      let mut ds = WeightedSets::with_capacity(linking, 4);
+      let x = ds.make_set();
+      let y = ds.make_set();
+      let z = ds.make_set();
+      assert!(!ds.same(x,y));
+      ds.union(x,y);
+      assert!(ds.same(x,y));
+      assert!(!ds.same(x,z));
+      let w = ds.union_find(z,y);
+      assert_eq!(ds.find(x), w);
+      assert!(ds.same(x,z));
+      assert_eq!(ds.linking(), linking);
+    }
+  }
+
+  fn depth(ds: &WeightedSets, mut p: Meta) -> u32 {
+    let mut d = 0;
+    while p != ds.parent(p) {
+      p = ds.parent(p);
+      d += 1
+    }
+    d
+  }
+
+  #[test]
+  fn balanced() {
+    for &linking in &[Linking::Size, Linking::Rank] {
+      let mut ds = WeightedSets::new(linking);
+      let ms: Vec<Meta> = (0..1024).map(|_| ds.make_set()).collect();
+      // naively linking the first root under the second would build a path here
+      for &m in &ms[1..] {
+        let r = ds.find(ms[0]);
+        ds.union(r, m);
+      }
+      assert!(ms.iter().all(|&m| depth(&ds, m) <= 1), "{:?}", linking);
+      assert_eq!(ms.iter().map(|&m| ds.find(m)).collect::<std::collections::HashSet<_>>().len(), 1);
+    }
+  }
+
+  #[test]
+  fn agree() {
+    let mut rng = Rng::new(0x51ed270b);
+    let mut all: Vec<WeightedSets> = LINKINGS.iter().map(|&l| WeightedSets::new(l)).collect();
+    for ds in &mut all {
+      for _ in 0..500 {
+        ds.make_set();
+      }
+    }
+    for _ in 0..2000 {
+      let (u, v) = (rng.meta(500), rng.meta(500));
+      if rng.below(3) == 0 {
+        let same: Vec<bool> = all.iter_mut().map(|ds| ds.same(u,v)).collect();
+        assert!(same.iter().all(|&s| s == same[0]));
+      } else {
+        for ds in &mut all {
+          ds.union(u,v);
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,290 @@
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+use {Links, Meta, Sets};
+
+// fully persistent disjoint sets, so that each branch of a proof search can hold its own
+// view of which metas are equal, and cloning the whole thing is O(1).
+//
+// the parents are kept in Conchon and Filliâtre's persistent arrays. only one version owns
+// the actual Vec, every other version is a chain of edits leading to it, and accessing a
+// version first reroots it, reversing the edits along the way, so the one we are working
+// on is always the cheap one. path compression doesn't change which sets a version
+// describes, so find_mut just replaces our own version with the compressed one.
+//
+// this is single threaded, see ConcurrentSets when the metas are shared.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Edit {
+  Set(Meta, Meta),
+  Push(Meta),
+  Pop,
+}
+
+impl Edit {
+  // returns the edit that takes it back
+  fn apply(self, parents: &mut Vec<Meta>) -> Edit {
+    match self {
+      Edit::Set(p, q) => Edit::Set(p, mem::replace(&mut parents[usize::from(p)], q)),
+      Edit::Push(p) => {
+        parents.push(p);
+        Edit::Pop
+      }
+      Edit::Pop => Edit::Push(parents.pop().unwrap()),
+    }
+  }
+}
+
+enum Data {
+  Arr(Vec<Meta>),
+  // this version is the other one with the edit applied
+  Diff(Edit, Version),
+}
+
+impl Data {
+  fn next(self) -> Option<Version> {
+    match self {
+      Data::Arr(_) => None,
+      Data::Diff(_, next) => Some(next),
+    }
+  }
+}
+
+struct Node(RefCell<Data>);
+
+type Version = Rc<Node>;
+
+fn take(v: &Version) -> Data { mem::replace(&mut *v.0.borrow_mut(), Data::Arr(Vec::new())) }
+
+// without this a long chain of edits would be dropped recursively
+impl Drop for Node {
+  fn drop(&mut self) {
+    let mut next = mem::replace(self.0.get_mut(), Data::Arr(Vec::new())).next();
+    while let Some(v) = next {
+      next = match Rc::try_unwrap(v) {
+        Ok(mut node) => mem::replace(node.0.get_mut(), Data::Arr(Vec::new())).next(),
+        Err(_) => None,
+      }
+    }
+  }
+}
+
+// make v the version that owns the Vec. this walks the chain first, rather than recursing
+// down it, as switching between distant branches can make it long
+fn reroot(v: &Version) {
+  let mut path = Vec::new();
+  let mut t = v.clone();
+  loop {
+    let next = match &*t.0.borrow() {
+      Data::Arr(_) => break,
+      Data::Diff(_, next) => next.clone(),
+    };
+    path.push(t);
+    t = next;
+  }
+  while let Some(t) = path.pop() {
+    let (edit, next) = match take(&t) {
+      Data::Diff(edit, next) => (edit, next),
+      Data::Arr(_) => unreachable!(),
+    };
+    let mut parents = match take(&next) {
+      Data::Arr(parents) => parents,
+      Data::Diff(..) => unreachable!(),
+    };
+    let inverse = edit.apply(&mut parents);
+    *next.0.borrow_mut() = Data::Diff(inverse, t.clone());
+    *t.0.borrow_mut() = Data::Arr(parents);
+  }
+}
+
+#[derive(Clone)]
+pub struct PersistentSets(Version);
+
+impl PersistentSets {
+  pub fn with_capacity(capacity: u32) -> Self {
+    PersistentSets::from(Sets::with_capacity(capacity))
+  }
+
+  pub fn new() -> Self { PersistentSets::from(Sets::new()) }
+
+  fn with<R, F: FnOnce(&Vec<Meta>) -> R>(&self, f: F) -> R {
+    reroot(&self.0);
+    match &*self.0.0.borrow() {
+      Data::Arr(parents) => f(parents),
+      Data::Diff(..) => unreachable!(),
+    }
+  }
+
+  // when no other version can see ours, we can edit it in place
+  fn edit(&mut self, edit: Edit) {
+    reroot(&self.0);
+    let mut parents = match take(&self.0) {
+      Data::Arr(parents) => parents,
+      Data::Diff(..) => unreachable!(),
+    };
+    let inverse = edit.apply(&mut parents);
+    if Rc::strong_count(&self.0) == 1 {
+      *self.0.0.borrow_mut() = Data::Arr(parents);
+    } else {
+      let new = Rc::new(Node(RefCell::new(Data::Arr(parents))));
+      *self.0.0.borrow_mut() = Data::Diff(inverse, new.clone());
+      self.0 = new;
+    }
+  }
+
+  pub fn make_set(&mut self) -> Meta {
+    let id = unsafe { Meta::new_unchecked(self.len()) };
+    self.edit(Edit::Push(id));
+    id
+  }
+
+  pub fn len(&self) -> u32 { self.with(|parents| u32::try_from(parents.len()).unwrap()) }
+
+  pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+  pub fn parent(&self, p: Meta) -> Meta { self.with(|parents| parents[usize::from(p)]) }
+
+  // find without self-modification
+  pub fn find(&self, mut p: Meta) -> Meta {
+    self.with(|parents| {
+      while p != parents[usize::from(p)] {
+        p = parents[usize::from(p)];
+      }
+      p
+    })
+  }
+
+  pub fn find_mut(&mut self, p: Meta) -> Meta { Links::find_mut(self, p) }
+
+  pub fn union(&mut self, u: Meta, v: Meta) -> Meta { Links::union(self, u, v) }
+
+  pub fn union_find(&mut self, u: Meta, v: Meta) -> Meta {
+    let w = self.union(u,v);
+    self.find_mut(w)
+  }
+
+  pub fn same(&mut self, u: Meta, v: Meta) -> bool { Links::same(self, u, v) }
+
+  pub fn to_sets(&self) -> Sets { self.with(|parents| Sets(parents.clone())) }
+}
+
+impl Default for PersistentSets {
+  fn default() -> Self { Self::new() }
+}
+
+impl From<Sets> for PersistentSets {
+  fn from(sets: Sets) -> Self { PersistentSets(Rc::new(Node(RefCell::new(Data::Arr(sets.0))))) }
+}
+
+impl Links for PersistentSets {
+  fn link(&self, p: Meta) -> Meta { self.parent(p) }
+  fn set_link(&mut self, p: Meta, q: Meta) {
+    if self.parent(p) != q {
+      self.edit(Edit::Set(p, q))
+    }
+  }
+}
+
+impl fmt::Debug for PersistentSets {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.with(|parents| f.debug_list().entries(parents).finish())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testing::{label, merge, Rng};
+
+  #[test]
+  fn it_works() {
+    let mut ds = PersistentSets::new();
+    let x = ds.make_set();
+    let y = ds.make_set();
+    let z = ds.make_set();
+    ds.union(x,y);
+    let mut left = ds.clone();
+    let mut right = ds.clone();
+    left.union(y,z);
+    let w = right.make_set();
+    right.union(w,z);
+    assert!(left.same(x,z));
+    assert!(!right.same(x,z));
+    assert!(right.same(w,z));
+    assert_eq!((ds.len(), left.len(), right.len()), (3, 3, 4));
+    assert!(!ds.same(x,z));
+    assert!(ds.same(x,y));
+    // compressing one version leaves the others as they were
+    assert_eq!(left.find_mut(x), left.find(z));
+    assert_eq!(ds.find(x), ds.find(y));
+    assert_ne!(ds.find(x), ds.find(z));
+    assert_eq!(right.union_find(x,w), right.find(z));
+  }
+
+  #[test]
+  fn long_histories() {
+    let mut ds = PersistentSets::new();
+    let mut versions = Vec::new();
+    for _ in 0..100000 {
+      let m = ds.make_set();
+      if m.u32() > 0 {
+        ds.union(m, Meta::new(m.u32() - 1).unwrap());
+      }
+      versions.push(ds.clone());
+    }
+    let first = Meta::new(0).unwrap();
+    // each of these walks the whole history
+    assert_eq!(versions[0].len(), 1);
+    assert_eq!(ds.find(first), ds.find(Meta::new(99999).unwrap()));
+    assert_eq!(versions[10].len(), 11);
+    assert_eq!(versions[10].find(first), versions[10].find(Meta::new(10).unwrap()));
+    assert_eq!(versions[99998].find(first), versions[99998].find(Meta::new(99998).unwrap()));
+    drop(versions);
+    assert_eq!(ds.len(), 100000);
+  }
+
+  #[test]
+  fn against_labels() {
+    let mut rng = Rng::new(0x9e3779b9);
+    let mut versions: Vec<(PersistentSets, Vec<u32>)> = vec![(PersistentSets::new(), Vec::new())];
+    for _ in 0..5000 {
+      let i = rng.below(versions.len() as u64) as usize;
+      match rng.below(8) {
+        0 if versions.len() < 32 => {
+          let branch = versions[i].clone();
+          versions.push(branch)
+        }
+        1 if versions.len() > 1 => {
+          versions.swap_remove(i);
+        }
+        2 | 3 => {
+          let (ds, labels) = &mut versions[i];
+          let m = ds.make_set();
+          labels.push(m.u32());
+        }
+        _ if !versions[i].1.is_empty() => {
+          let (ds, labels) = &mut versions[i];
+          let (u, v) = (rng.meta(ds.len()), rng.meta(ds.len()));
+          if rng.below(2) == 0 {
+            ds.union(u,v);
+            merge(labels, u, v);
+          } else {
+            assert_eq!(ds.same(u,v), label(labels, u) == label(labels, v));
+          }
+        }
+        _ => {}
+      }
+    }
+    for (ds, labels) in &versions {
+      assert_eq!(ds.len() as usize, labels.len());
+      for i in 0..ds.len() {
+        let u = Meta::new(i).unwrap();
+        let v = Meta::new(label(labels, u)).unwrap();
+        assert_eq!(ds.find(u), ds.find(v));
+      }
+    }
+  }
+}
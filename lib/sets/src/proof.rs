@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::mem;
+
+use {Linking, Meta, WeightedSets};
+
+// disjoint sets that remember why each union happened, so when unification fails we can
+// say how two metas came to be equal.
+//
+// alongside the sets we keep a proof forest, as in Nieuwenhuis and Oliveras, with an edge
+// for each union that did something, labelled with its reason. the edges of a class form
+// a tree, so there is exactly one path between any two of its members, and that path is
+// the explanation. to add an edge between two trees we reroot the smaller one at its end
+// of the edge, so the forest costs O(n log n) to build overall.
+
+#[derive(Debug, Clone)]
+pub struct ProofSets<R> {
+  sets: WeightedSets,
+  // each meta's parent in the proof forest, and the reason for the edge
+  proof: Vec<Option<(Meta, R)>>,
+  // the size of each class, kept at its root in sets
+  sizes: Vec<u32>,
+}
+
+// one union along an explanation, from one meta to another, though the union itself may
+// have been given them the other way around
+#[derive(Debug, PartialEq, Eq)]
+pub struct Step<'a, R> {
+  pub from: Meta,
+  pub to: Meta,
+  pub reason: &'a R,
+}
+
+impl<R> ProofSets<R> {
+  pub fn with_linking(linking: Linking) -> Self {
+    ProofSets { sets: WeightedSets::new(linking), proof: Vec::new(), sizes: Vec::new() }
+  }
+
+  pub fn new() -> Self { Self::with_linking(Linking::Index) }
+
+  pub fn make_set(&mut self) -> Meta {
+    self.proof.push(None);
+    self.sizes.push(1);
+    self.sets.make_set()
+  }
+
+  pub fn len(&self) -> u32 { self.sets.len() }
+
+  pub fn is_empty(&self) -> bool { self.sets.is_empty() }
+
+  pub fn find(&self, p: Meta) -> Meta { self.sets.find(p) }
+
+  pub fn find_mut(&mut self, p: Meta) -> Meta { self.sets.find_mut(p) }
+
+  pub fn same(&mut self, u: Meta, v: Meta) -> bool { self.sets.same(u,v) }
+
+  // make them equal because of reason. false if they already were, in which case the
+  // reason is dropped, as the existing explanation still stands
+  pub fn union(&mut self, u: Meta, v: Meta, reason: R) -> bool {
+    let ru = self.sets.find_mut(u);
+    let rv = self.sets.find_mut(v);
+    if ru == rv {
+      return false
+    }
+    let (su, sv) = (self.sizes[usize::from(ru)], self.sizes[usize::from(rv)]);
+    let (from, to) = if su < sv { (u, v) } else { (v, u) };
+    self.reroot(from);
+    self.proof[usize::from(from)] = Some((to, reason));
+    let r = self.sets.union_find(ru, rv);
+    self.sizes[usize::from(r)] = su + sv;
+    true
+  }
+
+  // make p the root of its proof tree by turning around the edges above it
+  fn reroot(&mut self, mut p: Meta) {
+    let mut incoming = None;
+    while let Some((up, reason)) = mem::replace(&mut self.proof[usize::from(p)], incoming) {
+      incoming = Some((p, reason));
+      p = up
+    }
+  }
+
+  fn proof_parent(&self, p: Meta) -> Option<(Meta, &R)> { self.proof[usize::from(p)].as_ref().map(|(q, r)| (*q, r)) }
+
+  // the unions that made u equal to v, in order along the way from u to v. None if they
+  // aren't equal, and no steps if they are the same meta
+  pub fn explain(&self, u: Meta, v: Meta) -> Option<Vec<Step<'_, R>>> {
+    if self.find(u) != self.find(v) {
+      return None
+    }
+    // u's ancestors in the proof forest
+    let mut ancestors = HashMap::new();
+    let mut p = Some(u);
+    while let Some(q) = p {
+      ancestors.insert(q, ancestors.len());
+      p = self.proof_parent(q).map(|(up, _)| up);
+    }
+    // climb from v until we meet one of them, which is where the two paths join
+    let mut down = Vec::new();
+    let mut q = v;
+    while !ancestors.contains_key(&q) {
+      let (up, reason) = self.proof_parent(q).unwrap();
+      down.push(Step { from: up, to: q, reason });
+      q = up
+    }
+    let mut steps = Vec::with_capacity(ancestors[&q] + down.len());
+    let mut p = u;
+    while p != q {
+      let (up, reason) = self.proof_parent(p).unwrap();
+      steps.push(Step { from: p, to: up, reason });
+      p = up
+    }
+    steps.extend(down.into_iter().rev());
+    Some(steps)
+  }
+
+  // the number of unions that did something, which is also the number of proof edges
+  pub fn unions(&self) -> u32 { u32::try_from(self.proof.iter().filter(|e| e.is_some()).count()).unwrap() }
+}
+
+impl<R> Default for ProofSets<R> {
+  fn default() -> Self { Self::new() }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testing::Rng;
+
+  #[test]
+  fn it_works() {
+    let mut ds = ProofSets::new();
+    let ms: Vec<Meta> = (0..5).map(|_| ds.make_set()).collect();
+    assert!(ds.union(ms[0], ms[1], "a"));
+    assert!(ds.union(ms[2], ms[3], "b"));
+    assert!(ds.union(ms[1], ms[3], "c"));
+    assert!(!ds.union(ms[0], ms[2], "redundant"));
+    assert_eq!(ds.explain(ms[0], ms[4]), None);
+    assert_eq!(ds.explain(ms[2], ms[2]), Some(Vec::new()));
+    let steps = ds.explain(ms[0], ms[2]).unwrap();
+    let reasons: Vec<&str> = steps.iter().map(|s| *s.reason).collect();
+    assert_eq!(reasons, vec!["a", "c", "b"]);
+    assert_eq!((steps[0].from, steps[2].to), (ms[0], ms[2]));
+    // and backwards
+    let reasons: Vec<&str> = ds.explain(ms[2], ms[0]).unwrap().iter().map(|s| *s.reason).collect();
+    assert_eq!(reasons, vec!["b", "c", "a"]);
+    assert_eq!(ds.unions(), 3);
+  }
+
+  #[test]
+  fn explanations_connect() {
+    for &linking in &[Linking::Index, Linking::Size, Linking::Rank] {
+      let mut rng = Rng::new(0x1f83d9ab);
+      let mut ds = ProofSets::with_linking(linking);
+      for _ in 0..300 {
+        ds.make_set();
+      }
+      for _ in 0..600 {
+        let (u, v) = (rng.meta(300), rng.meta(300));
+        // the reason records which union it was
+        ds.union(u, v, (u, v));
+        let (u, v) = (rng.meta(300), rng.meta(300));
+        match ds.explain(u, v) {
+          None => assert!(!ds.same(u,v)),
+          Some(steps) => {
+            let mut at = u;
+            for step in &steps {
+              assert_eq!(step.from, at);
+              let &(a, b) = step.reason;
+              assert!((a, b) == (step.from, step.to) || (b, a) == (step.from, step.to));
+              at = step.to;
+            }
+            assert_eq!(at, v);
+          }
+        }
+      }
+      assert_eq!(ds.unions() as usize + (0..300).filter(|&i| ds.find(Meta::new(i).unwrap()) == Meta::new(i).unwrap()).count(), 300);
+    }
+  }
+}
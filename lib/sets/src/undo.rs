@@ -0,0 +1,217 @@
+use std::convert::TryFrom;
+
+use {Links, Meta, Sets};
+
+// disjoint sets whose unions can be tried and then taken back, for elaboration that has
+// to attempt a unification and undo it if it fails.
+//
+// while a snapshot is open, every write to a parent link is logged, including the ones
+// made by path compression, so rolling back restores the exact links we had. outside of
+// any snapshot nothing is logged and this costs the same as Sets.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Undo {
+  MakeSet,
+  SetParent(Meta, Meta),
+}
+
+// snapshots must be rolled back or committed innermost first
+#[must_use]
+#[derive(Debug, PartialEq, Eq)]
+pub struct Snapshot {
+  log: usize,
+  depth: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UndoSets {
+  sets: Sets,
+  log: Vec<Undo>,
+  open: u32,
+}
+
+impl UndoSets {
+  pub fn with_capacity(capacity: u32) -> Self {
+    UndoSets { sets: Sets::with_capacity(capacity), log: Vec::new(), open: 0 }
+  }
+
+  pub fn new() -> Self { Self::default() }
+
+  pub fn make_set(&mut self) -> Meta {
+    if self.in_snapshot() {
+      self.log.push(Undo::MakeSet);
+    }
+    self.sets.make_set()
+  }
+
+  pub fn len(&self) -> u32 { self.sets.len() }
+
+  pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+  pub fn parent(&self, p: Meta) -> Meta { self.sets.parent(p) }
+
+  pub fn find(&self, p: Meta) -> Meta { self.sets.find(p) }
+
+  pub fn find_mut(&mut self, p: Meta) -> Meta { Links::find_mut(self, p) }
+
+  pub fn union(&mut self, u: Meta, v: Meta) -> Meta { Links::union(self, u, v) }
+
+  pub fn union_find(&mut self, u: Meta, v: Meta) -> Meta {
+    let w = self.union(u,v);
+    self.find_mut(w)
+  }
+
+  pub fn same(&mut self, u: Meta, v: Meta) -> bool { Links::same(self, u, v) }
+
+  pub fn in_snapshot(&self) -> bool { self.open > 0 }
+
+  pub fn snapshot(&mut self) -> Snapshot {
+    self.open += 1;
+    Snapshot { log: self.log.len(), depth: self.open }
+  }
+
+  // undoes everything since the snapshot was taken, including the work of any snapshots
+  // opened and committed inside it
+  pub fn rollback_to(&mut self, snapshot: Snapshot) {
+    self.close(&snapshot);
+    while self.log.len() > snapshot.log {
+      match self.log.pop().unwrap() {
+        Undo::MakeSet => {
+          self.sets.0.pop();
+        }
+        Undo::SetParent(p, old) => self.sets.0[usize::from(p)] = old,
+      }
+    }
+  }
+
+  // keeps everything since the snapshot. an enclosing snapshot can still roll it back
+  pub fn commit(&mut self, snapshot: Snapshot) {
+    self.close(&snapshot);
+    if !self.in_snapshot() {
+      self.log.clear();
+    }
+  }
+
+  fn close(&mut self, snapshot: &Snapshot) {
+    assert_eq!(snapshot.depth, self.open, "snapshots must be closed innermost first");
+    self.open -= 1;
+  }
+
+  // the entries that a rollback would have to undo
+  pub fn log_len(&self) -> u32 { u32::try_from(self.log.len()).unwrap() }
+
+  pub fn into_sets(self) -> Sets {
+    assert!(!self.in_snapshot(), "snapshot still open");
+    self.sets
+  }
+}
+
+impl From<Sets> for UndoSets {
+  fn from(sets: Sets) -> Self { UndoSets { sets, log: Vec::new(), open: 0 } }
+}
+
+impl Links for UndoSets {
+  fn link(&self, p: Meta) -> Meta { self.sets.parent(p) }
+  fn set_link(&mut self, p: Meta, q: Meta) {
+    let slot = &mut self.sets.0[usize::from(p)];
+    if self.open > 0 && *slot != q {
+      self.log.push(Undo::SetParent(p, *slot));
+    }
+    *slot = q
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use testing::{label, merge, Rng};
+
+  #[test]
+  fn it_works() {
+    let mut ds = UndoSets::new();
+    let x = ds.make_set();
+    let y = ds.make_set();
+    let z = ds.make_set();
+    ds.union(x,y);
+    let outer = ds.snapshot();
+    ds.union(y,z);
+    assert!(ds.same(x,z));
+    let inner = ds.snapshot();
+    let w = ds.make_set();
+    ds.union(w,x);
+    assert!(ds.same(w,z));
+    ds.rollback_to(inner);
+    assert_eq!(ds.len(), 3);
+    assert!(ds.same(x,z));
+    let inner = ds.snapshot();
+    let w = ds.make_set();
+    ds.union(z,w);
+    ds.commit(inner);
+    assert!(ds.same(x,w));
+    // the outer rollback takes the committed inner work with it
+    ds.rollback_to(outer);
+    assert_eq!(ds.len(), 3);
+    assert!(ds.same(x,y));
+    assert!(!ds.same(x,z));
+    assert_eq!(ds.log_len(), 0);
+  }
+
+  #[test]
+  #[should_panic(expected = "innermost first")]
+  fn out_of_order() {
+    let mut ds = UndoSets::new();
+    let outer = ds.snapshot();
+    let _inner = ds.snapshot();
+    ds.commit(outer);
+  }
+
+  #[test]
+  fn against_labels() {
+    let mut rng = Rng::new(0x2545f491);
+    let mut ds = UndoSets::new();
+    let mut labels: Vec<u32> = Vec::new();
+    let mut saved: Vec<(Snapshot, Vec<u32>)> = Vec::new();
+    for _ in 0..5000 {
+      match rng.below(10) {
+        0 | 1 => {
+          let m = ds.make_set();
+          labels.push(m.u32());
+        }
+        2 => saved.push((ds.snapshot(), labels.clone())),
+        3 => {
+          if let Some((s, old)) = saved.pop() {
+            ds.rollback_to(s);
+            labels = old;
+          }
+        }
+        4 => {
+          if let Some((s, _)) = saved.pop() {
+            ds.commit(s);
+          }
+        }
+        _ if !ds.is_empty() => {
+          let (u, v) = (rng.meta(ds.len()), rng.meta(ds.len()));
+          if rng.below(2) == 0 {
+            ds.union(u,v);
+            merge(&mut labels, u, v);
+          } else {
+            assert_eq!(ds.same(u,v), label(&labels, u) == label(&labels, v));
+            ds.find_mut(u);
+          }
+        }
+        _ => {}
+      }
+      assert_eq!(ds.len() as usize, labels.len());
+    }
+    while let Some((s, old)) = saved.pop() {
+      ds.rollback_to(s);
+      labels = old;
+    }
+    for i in 0..ds.len() {
+      for j in 0..ds.len() {
+        let (u, v) = (Meta::new(i).unwrap(), Meta::new(j).unwrap());
+        assert_eq!(ds.find(u) == ds.find(v), label(&labels, u) == label(&labels, v));
+      }
+    }
+  }
+}
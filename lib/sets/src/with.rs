@@ -0,0 +1,135 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use kanso_fun::algebra::Semigroup;
+
+use {Meta, Sets};
+
+// disjoint sets that carry a value for each class, such as the solution to a meta, its
+// type, or the constraints blocked on it. only roots hold a value, and when two classes
+// are merged their values are combined and moved to the new root.
+
+#[derive(Clone)]
+pub struct SetsWith<V> {
+  sets: Sets,
+  // Some exactly at the roots
+  values: Vec<Option<V>>,
+}
+
+impl<V> SetsWith<V> {
+  pub fn with_capacity(capacity: u32) -> Self {
+    SetsWith { sets: Sets::with_capacity(capacity), values: Vec::with_capacity(usize::try_from(capacity).unwrap()) }
+  }
+
+  pub fn new() -> Self { SetsWith { sets: Sets::new(), values: Vec::new() } }
+
+  pub fn make_set(&mut self, value: V) -> Meta {
+    self.values.push(Some(value));
+    self.sets.make_set()
+  }
+
+  pub fn len(&self) -> u32 { self.sets.len() }
+
+  pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+  pub fn parent(&self, p: Meta) -> Meta { self.sets.parent(p) }
+
+  pub fn find(&self, p: Meta) -> Meta { self.sets.find(p) }
+
+  pub fn find_mut(&mut self, p: Meta) -> Meta { self.sets.find_mut(p) }
+
+  pub fn same(&mut self, u: Meta, v: Meta) -> bool { self.sets.same(u,v) }
+
+  // the value of m's class, without compressing the path to it
+  pub fn get(&self, m: Meta) -> &V { self.values[usize::from(self.find(m))].as_ref().unwrap() }
+
+  pub fn value(&mut self, m: Meta) -> &V { self.value_mut(m) }
+
+  pub fn value_mut(&mut self, m: Meta) -> &mut V {
+    let r = self.find_mut(m);
+    self.values[usize::from(r)].as_mut().unwrap()
+  }
+
+  // replaces the value of m's class, returning the old one
+  pub fn set_value(&mut self, m: Meta, value: V) -> V { std::mem::replace(self.value_mut(m), value) }
+
+  // merges the classes of u and v, combining u's value with v's, and returns the new root.
+  // merge is only called if they were apart
+  pub fn union_with<F: FnOnce(V, V) -> V>(&mut self, u: Meta, v: Meta, merge: F) -> Meta {
+    let ru = self.find_mut(u);
+    let rv = self.find_mut(v);
+    if ru == rv {
+      return ru
+    }
+    let a = self.values[usize::from(ru)].take().unwrap();
+    let b = self.values[usize::from(rv)].take().unwrap();
+    let r = self.sets.union_find(ru, rv);
+    self.values[usize::from(r)] = Some(merge(a, b));
+    r
+  }
+
+  // every root along with the value of its class
+  pub fn classes(&self) -> impl Iterator<Item = (Meta, &V)> + '_ {
+    self.values.iter().enumerate().filter_map(|(i, v)| Some((Meta::new(u32::try_from(i).unwrap())?, v.as_ref()?)))
+  }
+}
+
+impl<V: Semigroup> SetsWith<V> {
+  // merges the classes of u and v, with value(u).op(value(v)), and returns the new root
+  pub fn union(&mut self, u: Meta, v: Meta) -> Meta { self.union_with(u, v, V::op) }
+}
+
+impl<V> Default for SetsWith<V> {
+  fn default() -> Self { Self::new() }
+}
+
+impl<V: fmt::Debug> fmt::Debug for SetsWith<V> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_map().entries(self.classes()).finish()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use kanso_fun::algebra::Max;
+
+  #[test]
+  fn it_works() {
+    let mut ds = SetsWith::new();
+    let x = ds.make_set(Max(3));
+    let y = ds.make_set(Max(5));
+    let z = ds.make_set(Max(1));
+    assert_eq!(*ds.value(x), Max(3));
+    let r = ds.union(x,z);
+    assert_eq!(ds.find(x), r);
+    assert_eq!(*ds.value(z), Max(3));
+    assert_eq!(*ds.get(y), Max(5));
+    ds.union(z,y);
+    assert!(ds.same(x,y));
+    assert_eq!(*ds.value(x), Max(5));
+    assert_eq!(ds.set_value(z, Max(0)), Max(5));
+    assert_eq!(*ds.get(y), Max(0));
+    assert_eq!(ds.classes().count(), 1);
+    // already together, so nothing is merged
+    ds.union_with(x, y, |_, _| unreachable!());
+  }
+
+  #[test]
+  fn merges_in_order() {
+    // constraint lists, kept in the order their classes were merged
+    let mut ds: SetsWith<Vec<u32>> = SetsWith::new();
+    let ms: Vec<Meta> = (0..8).map(|i| ds.make_set(vec![i])).collect();
+    let append = |mut a: Vec<u32>, b: Vec<u32>| {
+      a.extend(b);
+      a
+    };
+    ds.union_with(ms[2], ms[5], append);
+    ds.union_with(ms[7], ms[0], append);
+    ds.union_with(ms[5], ms[0], append);
+    ds.value_mut(ms[3]).push(9);
+    assert_eq!(*ds.value(ms[7]), vec![2, 5, 7, 0]);
+    assert_eq!(ds.classes().count(), 5);
+    assert_eq!(ds.classes().map(|(_, v)| v.len()).sum::<usize>(), 9);
+  }
+}
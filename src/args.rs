@@ -18,6 +18,14 @@ pub struct Arguments {
     #[clap(short, long, arg_enum, value_name = "SHELL")]
     pub completions: Option<Shell>,
 
+    /// Print hash-consing statistics on exit
+    #[clap(long, value_parser)]
+    pub stats: bool,
+
+    /// Rebuild module interfaces from source instead of reading cached .kani files
+    #[clap(long, value_parser)]
+    pub no_cache: bool,
+
     #[clap(subcommand)]
     pub command: Commands,
 }
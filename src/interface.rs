@@ -0,0 +1,379 @@
+// compiled module interfaces, so imports don't have to be elaborated from source every run
+//
+// a `.kani` file starts with a header: the magic bytes, the format version, and the
+// fingerprint of the source it was built from. then comes the interface itself, with the
+// name table it was written with, so it can be read back into a later run's table. the
+// fingerprint covers the module's source and the fingerprints of its dependencies, so a
+// change anywhere below a module invalidates it.
+//
+// the interface is generic in the terms it holds, so it doesn't fix a core syntax.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use name::{persist, Interner, Name};
+
+pub const MAGIC: [u8; 4] = *b"KANI";
+
+// bump whenever the layout of Interface, or of anything in it, changes
+pub const VERSION: u32 = 1;
+
+pub const EXTENSION: &str = "kani";
+
+// 128-bit FNV-1a, which is stable across runs, platforms and compiler versions, unlike
+// the hashers in std
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Fingerprint(u128);
+
+const FNV_OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+const FNV_PRIME: u128 = 0x0000000001000000000000000000013b;
+
+impl Fingerprint {
+  // of a module, given the fingerprints of what it imports, in import order
+  pub fn of(source: &[u8], deps: &[Fingerprint]) -> Fingerprint {
+    let mut h = FNV_OFFSET;
+    let mut write = |bytes: &[u8]| {
+      for &b in bytes {
+        h = (h ^ u128::from(b)).wrapping_mul(FNV_PRIME)
+      }
+    };
+    // the lengths keep the source and the dependencies from running into each other
+    write(&(source.len() as u64).to_le_bytes());
+    write(source);
+    write(&(deps.len() as u64).to_le_bytes());
+    for d in deps {
+      write(&d.0.to_le_bytes())
+    }
+    Fingerprint(h)
+  }
+
+  pub fn to_bytes(self) -> [u8; 16] { self.0.to_le_bytes() }
+
+  pub fn from_bytes(bytes: [u8; 16]) -> Fingerprint { Fingerprint(u128::from_le_bytes(bytes)) }
+}
+
+impl fmt::Display for Fingerprint {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{:032x}", self.0) }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Assoc {
+  Left,
+  Right,
+  Non,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Fixity {
+  pub assoc: Assoc,
+  pub prec: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Export<T> {
+  pub name: Name,
+  pub ty: T,
+  // for unfolding. None if the definition is opaque
+  pub def: Option<T>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interface<T> {
+  // the module's path, like [Data, List]
+  pub module: Vec<Name>,
+  // what it imports, with the fingerprints it was built against
+  pub deps: Vec<(Vec<Name>, Fingerprint)>,
+  pub exports: Vec<Export<T>>,
+  pub fixities: Vec<(Name, Fixity)>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+  Io(io::Error),
+  // the file doesn't start with MAGIC
+  NotAnInterface,
+  // written by another version of kanso
+  Incompatible { version: u32 },
+  Corrupt(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Error::Io(e) => write!(f, "{}", e),
+      Error::NotAnInterface => write!(f, "not a kanso interface file"),
+      Error::Incompatible { version } => write!(f, "interface file has version {}, but this kanso reads version {}", version, VERSION),
+      Error::Corrupt(e) => write!(f, "corrupt interface file: {}", e),
+    }
+  }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+  fn from(e: io::Error) -> Self { Error::Io(e) }
+}
+
+impl From<serde_json::Error> for Error {
+  fn from(e: serde_json::Error) -> Self {
+    if e.is_io() { Error::Io(e.into()) } else { Error::Corrupt(e) }
+  }
+}
+
+pub fn write<T: Serialize, I: Interner<Name>, W: Write>(names: &I, fingerprint: Fingerprint, interface: &Interface<T>, mut w: W) -> Result<(), Error> {
+  w.write_all(&MAGIC)?;
+  w.write_all(&VERSION.to_le_bytes())?;
+  w.write_all(&fingerprint.to_bytes())?;
+  persist::save(names, interface, &mut serde_json::Serializer::new(&mut w))?;
+  w.flush()?;
+  Ok(())
+}
+
+// checks the magic and the version, and returns the fingerprint, leaving the interface
+// itself unread
+pub fn read_header<R: Read>(r: &mut R) -> Result<Fingerprint, Error> {
+  let mut magic = [0; 4];
+  let mut version = [0; 4];
+  let mut fingerprint = [0; 16];
+  match r.read_exact(&mut magic) {
+    Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Err(Error::NotAnInterface),
+    r => r?,
+  }
+  if magic != MAGIC {
+    return Err(Error::NotAnInterface)
+  }
+  r.read_exact(&mut version)?;
+  let version = u32::from_le_bytes(version);
+  if version != VERSION {
+    return Err(Error::Incompatible { version })
+  }
+  r.read_exact(&mut fingerprint)?;
+  Ok(Fingerprint::from_bytes(fingerprint))
+}
+
+// the fingerprint and the interface, with its names interned into `names`
+pub fn read<T: DeserializeOwned, I: Interner<Name>, R: Read>(names: &mut I, mut r: R) -> Result<(Fingerprint, Interface<T>), Error> {
+  let fingerprint = read_header(&mut r)?;
+  Ok((fingerprint, read_body(names, r)?))
+}
+
+// what follows the header. a file that turns out to be corrupt may leave some of its
+// names in `names`, which is harmless
+fn read_body<T: DeserializeOwned, I: Interner<Name>, R: Read>(names: &mut I, r: R) -> Result<Interface<T>, Error> {
+  let mut de = serde_json::Deserializer::from_reader(r);
+  let interface = persist::load(names, &mut de)?;
+  de.end()?;
+  Ok(interface)
+}
+
+// where an interface came from
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Origin {
+  Cache,
+  Built,
+}
+
+// a directory of `.kani` files, one per module
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cache {
+  // None for --no-cache, which neither reads nor writes interfaces
+  dir: Option<PathBuf>,
+}
+
+impl Cache {
+  pub fn new<P: Into<PathBuf>>(dir: P) -> Self { Cache { dir: Some(dir.into()) } }
+
+  pub fn disabled() -> Self { Cache { dir: None } }
+
+  pub fn is_enabled(&self) -> bool { self.dir.is_some() }
+
+  pub fn dir(&self) -> Option<&Path> { self.dir.as_deref() }
+
+  // where the interface for the module lives, like `Data.List.kani`
+  pub fn path(&self, module: &str) -> Option<PathBuf> { Some(self.dir.as_ref()?.join(format!("{}.{}", module, EXTENSION))) }
+
+  // the cached interface, if there is one built from the same fingerprint. files that are
+  // stale, from another version, or unreadable are ignored, as they will be rebuilt
+  pub fn get<T: DeserializeOwned, I: Interner<Name>>(&self, names: &mut I, module: &str, fingerprint: Fingerprint) -> Option<Interface<T>> {
+    let mut r = BufReader::new(fs::File::open(self.path(module)?).ok()?);
+    if read_header(&mut r).ok()? != fingerprint {
+      return None
+    }
+    read_body(names, r).ok()
+  }
+
+  pub fn put<T: Serialize, I: Interner<Name>>(&self, names: &I, module: &str, fingerprint: Fingerprint, interface: &Interface<T>) -> Result<(), Error> {
+    let path = match self.path(module) {
+      Some(path) => path,
+      None => return Ok(()),
+    };
+    fs::create_dir_all(self.dir.as_ref().unwrap())?;
+    // write beside it and rename, so a run that dies halfway leaves the old file intact
+    let tmp = path.with_extension("kani.tmp");
+    write(names, fingerprint, interface, BufWriter::new(fs::File::create(&tmp)?))?;
+    fs::rename(&tmp, &path)?;
+    Ok(())
+  }
+
+  // the module's interface, from the cache if it is up to date, and otherwise built and
+  // written back
+  pub fn load_or_build<T, I, F>(&self, names: &mut I, module: &str, fingerprint: Fingerprint, build: F) -> Result<(Interface<T>, Origin), Error> where
+    T: Serialize + DeserializeOwned,
+    I: Interner<Name>,
+    F: FnOnce(&mut I) -> Interface<T>,
+  {
+    if let Some(interface) = self.get(names, module, fingerprint) {
+      return Ok((interface, Origin::Cache))
+    }
+    let interface = build(names);
+    self.put(names, module, fingerprint, &interface)?;
+    Ok((interface, Origin::Built))
+  }
+}
+
+impl fmt::Display for Cache {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match &self.dir {
+      Some(dir) => write!(f, "interfaces cached in {}", dir.display()),
+      None => write!(f, "interfaces not cached"),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use name::{names, Names, Resolver};
+  use std::env;
+  use std::process;
+
+  #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+  enum Ty {
+    Con(Name),
+    Arrow(Box<Ty>, Box<Ty>),
+  }
+
+  // Data.List, exporting map : (a -> b) -> b, with (++) infixr 5
+  fn interface(names: &mut Names) -> Interface<Ty> {
+    let (a, b) = (Name::letter('a').unwrap(), Name::letter('b').unwrap());
+    let arrow = |x, y| Ty::Arrow(Box::new(x), Box::new(y));
+    Interface {
+      module: vec![names.get_or_intern("Data"), names.get_or_intern("List")],
+      deps: vec![(vec![names.get_or_intern("Prelude")], Fingerprint::of(b"prelude", &[]))],
+      exports: vec![Export { name: names.get_or_intern("map"), ty: arrow(arrow(Ty::Con(a), Ty::Con(b)), Ty::Con(b)), def: None }],
+      fixities: vec![(names.get_or_intern("++"), Fixity { assoc: Assoc::Right, prec: 5 })],
+    }
+  }
+
+  fn strings(names: &Names, i: &Interface<Ty>) -> Vec<String> {
+    let mut out: Vec<String> = i.module.iter().map(|&n| names.resolve(&n).to_string()).collect();
+    out.extend(i.exports.iter().map(|e| names.resolve(&e.name).to_string()));
+    out.extend(i.fixities.iter().map(|&(n, _)| names.resolve(&n).to_string()));
+    out
+  }
+
+  fn scratch(tag: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("kanso-interface-{}-{}", process::id(), tag));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+  }
+
+  #[test]
+  fn fingerprints() {
+    let dep = Fingerprint::of(b"dep", &[]);
+    assert_eq!(Fingerprint::of(b"module", &[dep]), Fingerprint::of(b"module", &[dep]));
+    assert_ne!(Fingerprint::of(b"module", &[dep]), Fingerprint::of(b"module!", &[dep]));
+    assert_ne!(Fingerprint::of(b"module", &[dep]), Fingerprint::of(b"module", &[Fingerprint::of(b"dep!", &[])]));
+    assert_ne!(Fingerprint::of(b"module", &[]), Fingerprint::of(b"module", &[dep]));
+    // pinned, so the hash can't drift between builds unnoticed
+    assert_eq!(Fingerprint::of(b"", &[]).to_string(), "f1f90b7bf705b5ef1517163834145e4d");
+    assert_eq!(Fingerprint::from_bytes(dep.to_bytes()), dep);
+  }
+
+  #[test]
+  fn round_trip() {
+    let mut before = names();
+    let original = interface(&mut before);
+    let fingerprint = Fingerprint::of(b"module Data.List", &[original.deps[0].1]);
+    let mut file = Vec::new();
+    write(&before, fingerprint, &original, &mut file).unwrap();
+    assert_eq!(&file[..4], b"KANI");
+    let mut after = names();
+    after.get_or_intern("something else");
+    let (read_fingerprint, loaded) = read::<Ty, _, _>(&mut after, &file[..]).unwrap();
+    assert_eq!(read_fingerprint, fingerprint);
+    assert_eq!(strings(&after, &loaded), strings(&before, &original));
+    assert_eq!(loaded.exports[0].ty, original.exports[0].ty);
+    assert_eq!(loaded.fixities[0].1, Fixity { assoc: Assoc::Right, prec: 5 });
+  }
+
+  #[test]
+  fn rejects() {
+    let mut names = names();
+    let original = interface(&mut names);
+    let mut file = Vec::new();
+    write(&names, Fingerprint::of(b"", &[]), &original, &mut file).unwrap();
+    let mut future = file.clone();
+    future[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+    match read::<Ty, _, _>(&mut names, &future[..]) {
+      Err(Error::Incompatible { version }) => assert_eq!(version, VERSION + 1),
+      r => panic!("{:?}", r),
+    }
+    assert!(matches!(read::<Ty, _, _>(&mut names, &b"module Data.List"[..]), Err(Error::NotAnInterface)));
+    assert!(matches!(read::<Ty, _, _>(&mut names, &b""[..]), Err(Error::NotAnInterface)));
+    let truncated = &file[..file.len() - 3];
+    assert!(matches!(read::<Ty, _, _>(&mut names, truncated), Err(Error::Corrupt(_)) | Err(Error::Io(_))));
+  }
+
+  #[test]
+  fn caches() {
+    let dir = scratch("caches");
+    let cache = Cache::new(&dir);
+    let mut names = names();
+    let v1 = Fingerprint::of(b"v1", &[]);
+    let (built, origin) = cache.load_or_build(&mut names, "Data.List", v1, interface).unwrap();
+    assert_eq!(origin, Origin::Built);
+    assert!(dir.join("Data.List.kani").exists());
+    let (cached, origin) = cache.load_or_build(&mut names, "Data.List", v1, |_| panic!("rebuilt")).unwrap();
+    assert_eq!((cached, origin), (built.clone(), Origin::Cache));
+    // the source changed, so it is built again, and the file replaced
+    let v2 = Fingerprint::of(b"v2", &[]);
+    let (_, origin) = cache.load_or_build(&mut names, "Data.List", v2, interface).unwrap();
+    assert_eq!(origin, Origin::Built);
+    assert!(cache.get::<Ty, _>(&mut names, "Data.List", v1).is_none());
+    assert!(cache.get::<Ty, _>(&mut names, "Data.List", v2).is_some());
+    // a file from another version is rebuilt rather than read
+    let path = cache.path("Data.List").unwrap();
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[4..8].copy_from_slice(&0u32.to_le_bytes());
+    fs::write(&path, bytes).unwrap();
+    let (_, origin) = cache.load_or_build(&mut names, "Data.List", v2, interface).unwrap();
+    assert_eq!(origin, Origin::Built);
+    // as is one that is cut short
+    let mut bytes = fs::read(&path).unwrap();
+    let end = bytes.len() - 3;
+    bytes.truncate(end);
+    fs::write(&path, bytes).unwrap();
+    assert!(cache.get::<Ty, _>(&mut names, "Data.List", v2).is_none());
+    let (_, origin) = cache.load_or_build(&mut names, "Data.List", v2, interface).unwrap();
+    assert_eq!(origin, Origin::Built);
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn disabled() {
+    let cache = Cache::disabled();
+    let mut names = names();
+    let v1 = Fingerprint::of(b"v1", &[]);
+    for _ in 0..2 {
+      let (_, origin) = cache.load_or_build(&mut names, "Data.List", v1, interface).unwrap();
+      assert_eq!(origin, Origin::Built);
+    }
+    assert_eq!(cache.path("Data.List"), None);
+  }
+}
@@ -22,24 +22,26 @@ extern crate az;
 extern crate cfg_if;
 extern crate lasso;
 extern crate serde;
+extern crate serde_json;
 #[macro_use]
 extern crate salsa;
 #[macro_use]
 extern crate clap;
 extern crate clap_mangen;
 extern crate clap_complete;
+extern crate kanso_fun;
+
+// the data structures live in their own crates under lib/ so they can be used without
+// pulling in clap and salsa, and are re-exported here under their old module names
+pub extern crate kanso_lazy as lazy;
+pub extern crate kanso_hashcons as hc;
+pub extern crate kanso_sets as sets;
+pub extern crate kanso_egraph as egraph;
+
+pub use sets::meta;
+pub use kanso_fun::{algebra, cat, deque, finger, group_relative, hm, list, monoid_relative, skew, sync};
 
-pub mod lazy;
-pub mod hc;
-pub mod hm;
-pub mod meta;
-pub mod skew;
 pub mod name;
-pub mod sets;
+pub mod interface;
 pub mod args;
-pub mod cat;
-pub mod sync;
-pub mod list;
-pub mod algebra;
-pub mod group_relative;
 // pub mod make;
@@ -6,13 +6,22 @@ extern crate kanso;
 extern crate clap;
 
 use kanso::args::*;
+use kanso::interface::Cache;
+
+// where compiled module interfaces are kept, relative to where kanso is run
+const CACHE_DIR: &str = ".kanso";
 
 pub fn main() -> std::io::Result<()> {
   let args = get_args();
 
   match &args.command {
     Commands::Run { name } => {
+      // nothing is elaborated yet, so there are no interfaces to load through it
+      let cache = if args.no_cache { Cache::disabled() } else { Cache::new(CACHE_DIR) };
       println!("Running {}",name);
+      if args.verbose {
+        eprintln!("{}", cache);
+      }
     },
     Commands::Repl => {
       println!("Repling");
@@ -24,5 +33,8 @@ pub fn main() -> std::io::Result<()> {
       man_page(path.as_ref())?;
     }
   }
+  if args.stats {
+    eprintln!("hash consing: {}", kanso::hc::totals());
+  }
   Ok(())
 }
@@ -1,21 +1,377 @@
-use lasso::{Key, Rodeo, RodeoReader, RodeoResolver, Spur};
-use serde::{Serialize, Deserialize};
+use lasso::{Key, LassoResult, RodeoReader, RodeoResolver, Spur};
+#[cfg(not(feature = "parallel"))]
+use lasso::Rodeo;
+#[cfg(feature = "parallel")]
+use lasso::ThreadedRodeo;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
 use std::hash::Hash;
 
-#[derive(Serialize,Deserialize,Debug,Copy,Clone,PartialEq,Eq,PartialOrd,Ord,Hash)]
+use hc::{Constable, HashConstable, Hc, Stats};
+
+pub mod persist;
+
+#[derive(Serialize,Debug,Copy,Clone,PartialEq,Eq,PartialOrd,Ord,Hash)]
 #[repr(transparent)]
 pub struct Name(Spur);
 
-// TODO: set aside niches for _ as default() and maybe for a-z, use MSBs for counting?
 unsafe impl Key for Name {
-  unsafe fn into_usize(self) -> usize { self.0.into_usize() }
+  fn into_usize(self) -> usize { self.0.into_usize() }
   fn try_from_usize(int: usize) -> Option<Name> { Some(Name(Spur::try_from_usize(int)?)) }
 }
 
+// interned first into every Names, so they have the same keys in every table, and can be shown
+// without one
+const RESERVED: [&str; 27] = [
+  "_", "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m",
+  "n", "o", "p", "q", "r", "s", "t", "u", "v", "w", "x", "y", "z",
+];
+
+impl Name {
+  pub fn reserved(s: &str) -> Option<Name> { Name::try_from_usize(RESERVED.iter().position(|&r| r == s)?) }
+
+  pub fn letter(c: char) -> Option<Name> {
+    if c.is_ascii_lowercase() { Name::try_from_usize(c as usize - 'a' as usize + 1) } else { None }
+  }
+
+  pub fn as_reserved(self) -> Option<&'static str> { RESERVED.get(self.into_usize()).copied() }
+
+  pub fn is_wildcard(self) -> bool { self == Name::default() }
+
+  pub fn display(self, reader: &NameReader) -> NameDisplay<'_> { NameDisplay { name: self, reader } }
+}
+
+// `_`, in every table
 impl Default for Name {
   fn default() -> Self { Self::try_from_usize(0).unwrap() }
 }
 
-pub type Names = Rodeo<Name>;
+// with `parallel` the table can be shared between threads, and interned into through a
+// shared reference. either way, into_reader and into_resolver freeze it for the phases
+// that only need to look names up
+#[cfg(not(feature = "parallel"))]
+type Table = Rodeo<Name>;
+#[cfg(feature = "parallel")]
+type Table = ThreadedRodeo<Name>;
+
+// a table of names. the only way to make one is through `new`, which puts RESERVED first
+#[derive(Debug)]
+pub struct Names(Table);
+
 pub type NameReader = RodeoReader<Name>;
 pub type NaameResolver = RodeoResolver<Name>;
+
+// code that works with whichever table we were built with takes an `I: Interner<Name>`,
+// which Names and, with `parallel`, `&Names` both are
+pub use lasso::{Interner, Reader, Resolver};
+
+impl Names {
+  pub fn new() -> Self {
+    let mut names = Names(Table::new());
+    for r in RESERVED.iter() {
+      names.get_or_intern_static(r);
+    }
+    names
+  }
+
+  pub fn into_reader(self) -> NameReader { self.0.into_reader() }
+
+  pub fn into_resolver(self) -> NaameResolver { self.0.into_resolver() }
+}
+
+impl Default for Names {
+  fn default() -> Self { Self::new() }
+}
+
+pub fn names() -> Names { Names::new() }
+
+impl Interner<Name> for Names {
+  fn get_or_intern(&mut self, val: &str) -> Name { self.0.get_or_intern(val) }
+  fn try_get_or_intern(&mut self, val: &str) -> LassoResult<Name> { self.0.try_get_or_intern(val) }
+  fn get_or_intern_static(&mut self, val: &'static str) -> Name { self.0.get_or_intern_static(val) }
+  fn try_get_or_intern_static(&mut self, val: &'static str) -> LassoResult<Name> { self.0.try_get_or_intern_static(val) }
+}
+
+#[cfg(feature = "parallel")]
+impl Interner<Name> for &Names {
+  fn get_or_intern(&mut self, val: &str) -> Name { self.0.get_or_intern(val) }
+  fn try_get_or_intern(&mut self, val: &str) -> LassoResult<Name> { self.0.try_get_or_intern(val) }
+  fn get_or_intern_static(&mut self, val: &'static str) -> Name { self.0.get_or_intern_static(val) }
+  fn try_get_or_intern_static(&mut self, val: &'static str) -> LassoResult<Name> { self.0.try_get_or_intern_static(val) }
+}
+
+impl Reader<Name> for Names {
+  fn get(&self, val: &str) -> Option<Name> { self.0.get(val) }
+  fn contains(&self, val: &str) -> bool { self.0.contains(val) }
+}
+
+impl Resolver<Name> for Names {
+  fn resolve<'a>(&'a self, key: &Name) -> &'a str { self.0.resolve(key) }
+  fn try_resolve<'a>(&'a self, key: &Name) -> Option<&'a str> { self.0.try_resolve(key) }
+  unsafe fn resolve_unchecked<'a>(&'a self, key: &Name) -> &'a str { self.0.resolve_unchecked(key) }
+  fn contains_key(&self, key: &Name) -> bool { self.0.contains_key(key) }
+  fn len(&self) -> usize { self.0.len() }
+}
+
+#[derive(Copy,Clone)]
+pub struct NameDisplay<'a> {
+  name: Name,
+  reader: &'a NameReader,
+}
+
+impl<'a> fmt::Display for NameDisplay<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.name.as_reserved().or_else(|| self.reader.try_resolve(&self.name)) {
+      Some(s) => f.write_str(s),
+      None => write!(f, "?{}", self.name.into_usize()),
+    }
+  }
+}
+
+impl<'a> fmt::Debug for NameDisplay<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { fmt::Display::fmt(self, f) }
+}
+
+// qualified names, like `Data.List.map`. each is its last name along with the qualified
+// name it lives in, hash consed, so names in the same module share their prefix, and
+// comparing two of them is a pointer comparison
+
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+pub struct Segment {
+  parent: Option<QName>,
+  name: Name,
+}
+
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+#[repr(transparent)]
+pub struct QName(Hc<Segment>);
+
+impl QName {
+  pub fn name(&self) -> Name { self.0.name }
+
+  pub fn parent(&self) -> Option<&QName> { self.0.parent.as_ref() }
+
+  pub fn len(&self) -> usize { 1 + self.parent().map_or(0, QName::len) }
+
+  // never true, there is at least one name in a path
+  pub fn is_empty(&self) -> bool { false }
+
+  // outermost first
+  pub fn names(&self) -> Vec<Name> {
+    let mut names = Vec::with_capacity(self.len());
+    let mut q = Some(self);
+    while let Some(p) = q {
+      names.push(p.name());
+      q = p.parent();
+    }
+    names.reverse();
+    names
+  }
+
+  // whether self is other, or inside it
+  pub fn within(&self, other: &QName) -> bool {
+    let mut q = Some(self);
+    while let Some(p) = q {
+      if p == other {
+        return true
+      }
+      q = p.parent();
+    }
+    false
+  }
+
+  pub fn display<'a>(&'a self, reader: &'a NameReader) -> QNameDisplay<'a> { QNameDisplay { qname: self, reader } }
+}
+
+#[derive(Debug)]
+pub struct QNames(Constable<Segment>);
+
+impl QNames {
+  pub fn new() -> Self { QNames(Constable::new()) }
+
+  pub fn qualify(&mut self, parent: Option<&QName>, name: Name) -> QName {
+    QName(self.0.mk(Segment { parent: parent.cloned(), name }))
+  }
+
+  // outermost first. None if there are no names
+  pub fn path(&mut self, names: &[Name]) -> Option<QName> {
+    let (&first, rest) = names.split_first()?;
+    let mut q = self.qualify(None, first);
+    for &n in rest {
+      q = self.qualify(Some(&q), n);
+    }
+    Some(q)
+  }
+
+  pub fn stats(&self) -> Stats { self.0.stats() }
+}
+
+impl Default for QNames {
+  fn default() -> Self { Self::new() }
+}
+
+#[derive(Copy,Clone)]
+pub struct QNameDisplay<'a> {
+  qname: &'a QName,
+  reader: &'a NameReader,
+}
+
+impl<'a> fmt::Display for QNameDisplay<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if let Some(p) = self.qname.parent() {
+      write!(f, "{}.", p.display(self.reader))?;
+    }
+    write!(f, "{}", self.qname.name().display(self.reader))
+  }
+}
+
+impl<'a> fmt::Debug for QNameDisplay<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { fmt::Display::fmt(self, f) }
+}
+
+// the names bound around some point, in order, so we can pick fresh ones that don't
+// shadow any of them when printing a binder or opening a module
+#[derive(Debug,Clone,Default)]
+pub struct Scope {
+  bound: Vec<Name>,
+  counts: HashMap<Name, u32>,
+}
+
+impl Scope {
+  pub fn new() -> Self { Self::default() }
+
+  pub fn len(&self) -> usize { self.bound.len() }
+
+  pub fn is_empty(&self) -> bool { self.bound.is_empty() }
+
+  pub fn contains(&self, n: Name) -> bool { self.counts.contains_key(&n) }
+
+  pub fn bind(&mut self, n: Name) {
+    self.bound.push(n);
+    *self.counts.entry(n).or_insert(0) += 1
+  }
+
+  // the innermost name
+  pub fn unbind(&mut self) -> Option<Name> {
+    let n = self.bound.pop()?;
+    let count = self.counts.get_mut(&n).unwrap();
+    *count -= 1;
+    if *count == 0 {
+      self.counts.remove(&n);
+    }
+    Some(n)
+  }
+
+  pub fn truncate(&mut self, len: usize) {
+    while self.bound.len() > len {
+      self.unbind();
+    }
+  }
+
+  // binds and returns the hint if it is free, otherwise the first of hint₁, hint₂, ...
+  // that is. `_` can't be referred to, so it never clashes
+  pub fn fresh<I: Interner<Name>>(&mut self, names: &mut I, hint: Name) -> Name {
+    let name = if hint.is_wildcard() || !self.contains(hint) {
+      hint
+    } else {
+      let base = strip_subscript(names.resolve(&hint)).to_string();
+      (1..).map(|i| names.get_or_intern(&format!("{}{}", base, subscript(i)))).find(|&n| !self.contains(n)).unwrap()
+    };
+    self.bind(name);
+    name
+  }
+}
+
+pub fn subscript(n: u32) -> String {
+  n.to_string().chars().map(|d| char::from_u32('₀' as u32 + d.to_digit(10).unwrap()).unwrap()).collect()
+}
+
+fn strip_subscript(s: &str) -> &str { s.trim_end_matches(|c| ('₀'..='₉').contains(&c)) }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reserved() {
+    let mut names = names();
+    assert_eq!(names.get_or_intern("_"), Name::default());
+    assert_eq!(names.get_or_intern("x"), Name::letter('x').unwrap());
+    assert_eq!(Name::reserved("q"), Name::letter('q'));
+    assert_eq!(Name::letter('X'), None);
+    let foo = names.get_or_intern("foo");
+    assert_eq!(foo.as_reserved(), None);
+    assert!(Name::default().is_wildcard());
+    // however the table is made
+    assert_eq!(Names::default().get("_"), Some(Name::default()));
+    let reader = names.into_reader();
+    assert_eq!(format!("{} {}", Name::default().display(&reader), foo.display(&reader)), "_ foo");
+  }
+
+  #[test]
+  fn qualified() {
+    let mut names = names();
+    let mut qnames = QNames::new();
+    let (data, list, map) = (names.get_or_intern("Data"), names.get_or_intern("List"), names.get_or_intern("map"));
+    let m = qnames.path(&[data, list]).unwrap();
+    let q = qnames.qualify(Some(&m), map);
+    assert_eq!(qnames.path(&[data, list, map]), Some(q.clone()));
+    assert_eq!(q.names(), vec![data, list, map]);
+    assert_eq!(q.len(), 3);
+    assert!(q.within(&m));
+    assert!(!m.within(&q));
+    assert_eq!(qnames.path(&[]), None);
+    let reader = names.into_reader();
+    assert_eq!(q.display(&reader).to_string(), "Data.List.map");
+  }
+
+  #[test]
+  fn fresh() {
+    let mut names = names();
+    let x = Name::letter('x').unwrap();
+    let mut scope = Scope::new();
+    assert_eq!(scope.fresh(&mut names, x), x);
+    let x1 = scope.fresh(&mut names, x);
+    let x2 = scope.fresh(&mut names, x);
+    // subscripts are counted from the base, not stacked
+    let x3 = scope.fresh(&mut names, x1);
+    assert_eq!([names.resolve(&x1), names.resolve(&x2), names.resolve(&x3)], ["x₁", "x₂", "x₃"]);
+    assert_eq!(scope.fresh(&mut names, Name::default()), Name::default());
+    assert_eq!(scope.fresh(&mut names, Name::default()), Name::default());
+    scope.truncate(2);
+    assert_eq!(scope.fresh(&mut names, x), x2);
+    assert_eq!(scope.unbind(), Some(x2));
+    assert!(scope.contains(x1));
+    assert!(!scope.contains(x2));
+    assert_eq!(subscript(10), "₁₀");
+  }
+
+  #[cfg(feature = "parallel")]
+  #[test]
+  fn shared_across_threads() {
+    let names = names();
+    let interned: Vec<Vec<Name>> = std::thread::scope(|s| {
+      let handles: Vec<_> = (0..8)
+        .map(|t| {
+          let shared = &names;
+          s.spawn(move || {
+            let mut names = shared;
+            (0..1000).map(|i| names.get_or_intern(&format!("n{}", (i * 7 + t) % 500))).collect::<Vec<_>>()
+          })
+        })
+        .collect();
+      handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert_eq!(names.len(), RESERVED.len() + 500);
+    for (t, keys) in interned.iter().enumerate() {
+      for (i, &k) in keys.iter().enumerate() {
+        assert_eq!(names.resolve(&k), format!("n{}", (i * 7 + t) % 500));
+      }
+    }
+    // the reserved names kept their keys
+    assert_eq!(names.get("x"), Name::letter('x'));
+    let reader = names.into_reader();
+    assert_eq!(interned[3][0].display(&reader).to_string(), "n3");
+  }
+}
@@ -0,0 +1,202 @@
+// name tables for things written to disk
+//
+// a Name serializes as its key, which only means something to the table that interned it.
+// `save` writes the table's strings, in key order, ahead of the value, and `load` interns
+// them into the current table and rewrites each key it reads through the result, so an
+// artifact can be read back by a later run that interned its names in another order.
+//
+// the whole table is written, not just the names the value uses, so the value only has
+// to be serialized once. a name read outside of `load` is an error, as its key could mean
+// anything, unless it is read by `load_raw`, for values that never left this process.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::marker::PhantomData;
+
+use lasso::{Key, Spur};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{Interner, Name};
+
+enum Loading {
+  // what each key in the file became in the current table
+  Table(Vec<Name>),
+  // keys from this process's table, taken as they are
+  Raw,
+}
+
+thread_local! {
+  static LOADING: RefCell<Option<Loading>> = const { RefCell::new(None) };
+}
+
+// ends the session even if deserialization panics
+struct Session;
+
+impl Drop for Session {
+  fn drop(&mut self) { LOADING.with(|s| *s.borrow_mut() = None) }
+}
+
+const FIELDS: &[&str] = &["names", "value"];
+
+struct Saving<'a, T: ?Sized, I> {
+  names: &'a I,
+  value: &'a T,
+}
+
+impl<'a, T: ?Sized + Serialize, I: Interner<Name>> Serialize for Saving<'a, T, I> {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let strings: Vec<&str> = (0..self.names.len()).map(|i| self.names.resolve(&Name::try_from_usize(i).unwrap())).collect();
+    let mut s = serializer.serialize_struct("Named", 2)?;
+    s.serialize_field("names", &strings)?;
+    s.serialize_field("value", self.value)?;
+    s.end()
+  }
+}
+
+// the value, preceded by the table its names were interned in
+pub fn save<T: ?Sized + Serialize, I: Interner<Name>, S: Serializer>(names: &I, value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+  Saving { names, value }.serialize(serializer)
+}
+
+// reads what `save` wrote, interning its names into `names`
+pub fn load<'de, T: Deserialize<'de>, I: Interner<Name>, D: Deserializer<'de>>(names: &mut I, deserializer: D) -> Result<T, D::Error> {
+  if loading() {
+    return Err(de::Error::custom("name tables can't be loaded inside one another"))
+  }
+  deserializer.deserialize_struct("Named", FIELDS, NamedVisitor { names, value: PhantomData })
+}
+
+// reads a value whose names were interned by this process, into the same table, as their
+// keys. anything from outside the process should go through `save` and `load`
+pub fn load_raw<'de, T: Deserialize<'de>, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+  if loading() {
+    return Err(de::Error::custom("name tables can't be loaded inside one another"))
+  }
+  LOADING.with(|s| *s.borrow_mut() = Some(Loading::Raw));
+  let _session = Session;
+  T::deserialize(deserializer)
+}
+
+fn loading() -> bool { LOADING.with(|s| s.borrow().is_some()) }
+
+// interns the strings, and starts remapping keys through them
+fn start<I: Interner<Name>>(names: &mut I, strings: Vec<String>) -> Session {
+  let table = strings.iter().map(|s| names.get_or_intern(s)).collect();
+  LOADING.with(|s| *s.borrow_mut() = Some(Loading::Table(table)));
+  Session
+}
+
+struct NamedVisitor<'a, T, I> {
+  names: &'a mut I,
+  value: PhantomData<T>,
+}
+
+#[derive(Deserialize)]
+#[serde(field_identifier, rename_all = "lowercase")]
+enum Field {
+  Names,
+  Value,
+}
+
+impl<'a, 'de, T: Deserialize<'de>, I: Interner<Name>> Visitor<'de> for NamedVisitor<'a, T, I> {
+  type Value = T;
+
+  fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result { write!(fmt, "a name table and a value") }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<T, A::Error> {
+    let strings: Vec<String> = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+    let _session = start(self.names, strings);
+    seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<T, A::Error> {
+    match map.next_key()? {
+      Some(Field::Names) => {}
+      Some(Field::Value) => return Err(de::Error::custom("the name table must come before the value")),
+      None => return Err(de::Error::missing_field("names")),
+    }
+    let strings: Vec<String> = map.next_value()?;
+    let _session = start(self.names, strings);
+    match map.next_key()? {
+      Some(Field::Value) => map.next_value(),
+      Some(Field::Names) => Err(de::Error::duplicate_field("names")),
+      None => Err(de::Error::missing_field("value")),
+    }
+  }
+}
+
+// the shape Name had when it derived Deserialize
+#[derive(Deserialize)]
+#[serde(rename = "Name")]
+struct Raw(Spur);
+
+impl<'de> Deserialize<'de> for Name {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let Raw(key) = Raw::deserialize(deserializer)?;
+    LOADING.with(|s| match s.borrow().as_ref() {
+      None => Err(de::Error::custom("names can only be read along with their table, by name::persist::load")),
+      Some(Loading::Raw) => Ok(Name(key)),
+      Some(Loading::Table(table)) => table.get(key.into_usize()).copied().ok_or_else(|| {
+        de::Error::custom(format_args!("name {} is not in the name table", key.into_usize()))
+      }),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use name::{names, Resolver};
+  use serde_json;
+
+  #[derive(Debug, PartialEq, Serialize, Deserialize)]
+  struct Interface {
+    exports: Vec<Name>,
+    imports: Vec<(Name, Vec<Name>)>,
+  }
+
+  #[test]
+  fn remaps() {
+    let mut before = names();
+    let (map, fold, list) = (before.get_or_intern("map"), before.get_or_intern("fold"), before.get_or_intern("List"));
+    let x = Name::letter('x').unwrap();
+    let interface = Interface { exports: vec![map, fold, x], imports: vec![(list, vec![map, Name::default()])] };
+    let mut out = Vec::new();
+    save(&before, &interface, &mut serde_json::Serializer::new(&mut out)).unwrap();
+    // a later run, which has seen other names first
+    let mut after = names();
+    after.get_or_intern("unrelated");
+    after.get_or_intern("fold");
+    let mut de = serde_json::Deserializer::from_slice(&out);
+    let loaded: Interface = load(&mut after, &mut de).unwrap();
+    let exports: Vec<&str> = loaded.exports.iter().map(|&n| after.resolve(&n)).collect();
+    assert_eq!(exports, vec!["map", "fold", "x"]);
+    assert_ne!(loaded.exports[0], map);
+    assert_eq!(loaded.exports[2], x);
+    assert_eq!(after.resolve(&loaded.imports[0].0), "List");
+    assert_eq!(loaded.imports[0].1, vec![loaded.exports[0], Name::default()]);
+    // within this process the keys can be read back as they are, but only if asked for
+    let json = serde_json::to_string(&map).unwrap();
+    assert!(serde_json::from_str::<Name>(&json).is_err());
+    let raw: Name = load_raw(&mut serde_json::Deserializer::from_str(&json)).unwrap();
+    assert_eq!(raw, map);
+  }
+
+  #[test]
+  fn rejects() {
+    let mut names = names();
+    // 1000 is well past the end of a table of two strings
+    let bad = r#"{"names":["_","a"],"value":[1000]}"#;
+    let err = load::<Vec<Name>, _, _>(&mut names, &mut serde_json::Deserializer::from_str(bad)).unwrap_err();
+    assert!(err.to_string().contains("not in the name table"), "{}", err);
+    let backwards = r#"{"value":[],"names":[]}"#;
+    assert!(load::<Vec<Name>, _, _>(&mut names, &mut serde_json::Deserializer::from_str(backwards)).is_err());
+    // a failed load doesn't leave its table behind
+    let err = serde_json::from_str::<Name>("3").unwrap_err();
+    assert!(err.to_string().contains("along with their table"), "{}", err);
+    let two: Name = load_raw(&mut serde_json::Deserializer::from_str("3")).unwrap();
+    assert_eq!(two.into_usize(), 2);
+  }
+}